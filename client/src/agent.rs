@@ -0,0 +1,400 @@
+/*
+ * ----------------------------------------------------------------------------
+ * Project:     Personal Password Manager
+ * File:        agent.rs
+ * Description: Background agent holding the key derived from the master
+ *              password for a while, so that later runs of the client can skip
+ *              the password prompt and key derivation.
+ *
+ * Author:      RoscoeEH
+ * ---------------------------------------------------------------------------
+ */
+
+use std::error::Error;
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+#[cfg(unix)]
+use std::time::Duration;
+#[cfg(unix)]
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::time::Instant;
+
+#[cfg(unix)]
+use crate::session::SessionKey;
+
+// Environment variable naming the agent socket, much like SSH_AUTH_SOCK
+const SOCKET_ENV: &str = "PM_AGENT_SOCK";
+const SOCKET_NAME: &str = "password_manager_agent.sock";
+
+// Request types, framed the same way as requests to the server
+const GET_KEY: u8 = 1;
+const ADD_KEY: u8 = 2;
+const LOCK: u8 = 3;
+
+// Longest a client may take over a request before the agent moves on to the next
+#[cfg(unix)]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn socket_path() -> PathBuf {
+    runtime_path(SOCKET_ENV, SOCKET_NAME)
+}
+
+// Location of a socket: the path in the environment variable if set, otherwise
+// the per-user runtime directory, falling back to a private directory under /tmp
+pub fn runtime_path(env: &str, name: &str) -> PathBuf {
+    if let Ok(path) = std::env::var(env) {
+        return PathBuf::from(path);
+    }
+    match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => PathBuf::from(dir).join(name),
+        Err(_) => private_dir().join(name),
+    }
+}
+
+#[cfg(unix)]
+fn private_dir() -> PathBuf {
+    let uid = unsafe { libc::getuid() };
+    std::env::temp_dir().join(format!("password-manager-{}", uid))
+}
+
+#[cfg(not(unix))]
+fn private_dir() -> PathBuf {
+    std::env::temp_dir()
+}
+
+// Makes sure the directory holding the socket exists and only its owner can reach it
+#[cfg(unix)]
+fn prepare_dir(path: &std::path::Path) -> Result<(), Box<dyn Error>> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let dir = path.parent().ok_or("Invalid socket path")?;
+    if !dir.exists() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+
+    let metadata = std::fs::metadata(dir)?;
+    if metadata.uid() != unsafe { libc::getuid() } || metadata.mode() & 0o077 != 0 {
+        return Err(format!(
+            "{} must be owned by you and closed to other users",
+            dir.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// Listens on a socket that only its owner can reach, replacing one left behind by
+// an earlier run that didn't exit cleanly
+#[cfg(unix)]
+pub async fn bind(path: &std::path::Path) -> Result<UnixListener, Box<dyn Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    prepare_dir(path)?;
+
+    // A socket that nothing answers on is stale
+    if UnixStream::connect(path).await.is_ok() {
+        return Err(format!("An agent is already running at {}", path.display()).into());
+    }
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+// Whether a client connected to a socket runs as the same user as this process. The
+// socket's directory already shuts out other users, but a path from the environment
+// may not.
+#[cfg(unix)]
+pub fn same_user(stream: &UnixStream) -> bool {
+    stream
+        .peer_cred()
+        .is_ok_and(|cred| cred.uid() == unsafe { libc::getuid() })
+}
+
+#[cfg(unix)]
+async fn send(
+    stream: &mut UnixStream,
+    message_type: u8,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let mut message = Zeroizing::new(vec![message_type]);
+    message.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    message.extend_from_slice(data);
+
+    stream.write_all(&message).await?;
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn receive(stream: &mut UnixStream) -> Result<(u8, Zeroizing<Vec<u8>>), Box<dyn Error>> {
+    let mut header = [0; 5];
+    stream.read_exact(&mut header).await?;
+
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    // Nothing sent to the agent is bigger than a key
+    if length > 32 {
+        return Err("Agent message too long".into());
+    }
+    let mut data = Zeroizing::new(vec![0; length]);
+    stream.read_exact(&mut data).await?;
+    Ok((header[0], data))
+}
+
+// Sends a request to the running agent, turning agent error responses into errors
+#[cfg(unix)]
+async fn request(request_type: u8, data: &[u8]) -> Result<Zeroizing<Vec<u8>>, Box<dyn Error>> {
+    let mut stream = UnixStream::connect(socket_path()).await?;
+    send(&mut stream, request_type, data).await?;
+    let (response_type, data) = receive(&mut stream).await?;
+
+    if response_type == 0 {
+        return Err(String::from_utf8_lossy(&data).into_owned().into());
+    }
+    if response_type != request_type {
+        return Err(format!("Unexpected agent response type {}", response_type).into());
+    }
+    Ok(data)
+}
+
+// Key held by a running agent, or None if there is no agent or it has no key
+#[cfg(unix)]
+pub async fn fetch_key() -> Option<Zeroizing<[u8; 32]>> {
+    let data = request(GET_KEY, b"").await.ok()?;
+    let mut key = Zeroizing::new([0; 32]);
+    if data.len() != key.len() {
+        return None;
+    }
+    key.copy_from_slice(&data);
+    Some(key)
+}
+
+#[cfg(not(unix))]
+pub async fn fetch_key() -> Option<Zeroizing<[u8; 32]>> {
+    None
+}
+
+// Hands a key that was just derived to the running agent
+#[cfg(unix)]
+pub async fn add_key(key: &[u8; 32]) -> Result<(), Box<dyn Error>> {
+    request(ADD_KEY, key).await?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn add_key(_key: &[u8; 32]) -> Result<(), Box<dyn Error>> {
+    Err("The agent is only supported on Unix".into())
+}
+
+// Tells the running agent to wipe its key
+#[cfg(unix)]
+pub async fn lock() -> Result<(), Box<dyn Error>> {
+    request(LOCK, b"").await.map_err(|e| {
+        format!(
+            "Failed to reach the agent at {}: {}",
+            socket_path().display(),
+            e
+        )
+    })?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn lock() -> Result<(), Box<dyn Error>> {
+    Err("The agent is only supported on Unix".into())
+}
+
+// Runs the agent until it is interrupted or terminated. The key it is given is
+// wiped ttl after it arrives, or kept until locked if ttl is zero.
+#[cfg(unix)]
+pub async fn serve(ttl: Duration) -> Result<(), Box<dyn Error>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let path = socket_path();
+    let listener = bind(&path).await?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    println!("Agent listening on {}", path.display());
+
+    let key = SessionKey::new();
+    let mut expires: Option<Instant> = None;
+
+    loop {
+        let expiry = tokio::time::sleep_until(expires.unwrap_or_else(Instant::now));
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (mut stream, _) = accepted?;
+                let handled = tokio::time::timeout(
+                    REQUEST_TIMEOUT,
+                    handle(&mut stream, &key, &mut expires, ttl),
+                )
+                .await;
+                match handled {
+                    Ok(Err(e)) => eprintln!("Agent request failed: {}", e),
+                    Err(_) => eprintln!("Agent request timed out"),
+                    Ok(Ok(())) => {}
+                }
+            }
+            _ = expiry, if expires.is_some() => {
+                key.lock();
+                expires = None;
+            }
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        }
+    }
+
+    key.lock();
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn serve(_ttl: std::time::Duration) -> Result<(), Box<dyn Error>> {
+    Err("The agent is only supported on Unix".into())
+}
+
+// Answers a single request from a client
+#[cfg(unix)]
+async fn handle(
+    stream: &mut UnixStream,
+    key: &SessionKey,
+    expires: &mut Option<Instant>,
+    ttl: Duration,
+) -> Result<(), Box<dyn Error>> {
+    if !same_user(stream) {
+        return send(stream, 0, b"Permission denied").await;
+    }
+
+    let (request_type, data) = receive(stream).await?;
+    match request_type {
+        GET_KEY => match key.with(|key| Zeroizing::new(key.to_vec())) {
+            Some(key) => send(stream, GET_KEY, &key).await,
+            None => send(stream, 0, b"Agent is locked").await,
+        },
+        ADD_KEY => {
+            let Ok(new_key) = <&[u8; 32]>::try_from(data.as_slice()) else {
+                return send(stream, 0, b"Invalid key").await;
+            };
+            if let Err(e) = key.unlock(new_key) {
+                eprintln!("Warning: failed to lock the key in memory: {}", e);
+            }
+            *expires = (!ttl.is_zero()).then(|| Instant::now() + ttl);
+            send(stream, ADD_KEY, b"").await
+        }
+        LOCK => {
+            key.lock();
+            *expires = None;
+            send(stream, LOCK, b"").await
+        }
+        _ => send(stream, 0, b"Unknown request").await,
+    }
+}
+
+// Sends a single request through handle and returns the agent's response
+#[cfg(all(test, unix))]
+async fn exchange(
+    key: &SessionKey,
+    expires: &mut Option<Instant>,
+    ttl: Duration,
+    request_type: u8,
+    data: &[u8],
+) -> (u8, Vec<u8>) {
+    let (mut client, mut agent) = UnixStream::pair().unwrap();
+    send(&mut client, request_type, data).await.unwrap();
+    handle(&mut agent, key, expires, ttl).await.unwrap();
+    let (response_type, data) = receive(&mut client).await.unwrap();
+    (response_type, data.to_vec())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn messages_keep_their_framing() {
+    let (mut client, mut agent) = UnixStream::pair().unwrap();
+
+    // Messages sent back to back are read one at a time
+    send(&mut client, ADD_KEY, &[7; 32]).await.unwrap();
+    send(&mut client, LOCK, b"").await.unwrap();
+    let (request_type, data) = receive(&mut agent).await.unwrap();
+    assert_eq!((request_type, data.to_vec()), (ADD_KEY, vec![7; 32]));
+    let (request_type, data) = receive(&mut agent).await.unwrap();
+    assert_eq!((request_type, data.to_vec()), (LOCK, Vec::new()));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn oversized_messages_are_rejected() {
+    let (mut client, mut agent) = UnixStream::pair().unwrap();
+
+    // The length is checked before any of the data is read
+    let mut header = vec![ADD_KEY];
+    header.extend_from_slice(&33u32.to_be_bytes());
+    client.write_all(&header).await.unwrap();
+    assert!(receive(&mut agent).await.is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn add_key_takes_only_whole_keys() {
+    let key = SessionKey::new();
+    let mut expires = None;
+    let ttl = Duration::from_secs(60);
+
+    let response = exchange(&key, &mut expires, ttl, ADD_KEY, &[7; 16]).await;
+    assert_eq!(response, (0, b"Invalid key".to_vec()));
+    assert!(!key.is_unlocked());
+    assert!(expires.is_none());
+
+    let response = exchange(&key, &mut expires, ttl, ADD_KEY, &[7; 32]).await;
+    assert_eq!(response, (ADD_KEY, Vec::new()));
+    assert!(expires.is_some());
+    let response = exchange(&key, &mut expires, ttl, GET_KEY, b"").await;
+    assert_eq!(response, (GET_KEY, vec![7; 32]));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn lock_wipes_the_key() {
+    let key = SessionKey::new();
+    let mut expires = None;
+    let ttl = Duration::from_secs(60);
+
+    exchange(&key, &mut expires, ttl, ADD_KEY, &[7; 32]).await;
+    let response = exchange(&key, &mut expires, ttl, LOCK, b"").await;
+    assert_eq!(response, (LOCK, Vec::new()));
+    assert!(expires.is_none());
+
+    let response = exchange(&key, &mut expires, ttl, GET_KEY, b"").await;
+    assert_eq!(response, (0, b"Agent is locked".to_vec()));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn key_is_wiped_after_ttl() {
+    // Only this test runs an agent, so it has the socket variable to itself
+    let dir = std::env::temp_dir().join(format!("password-manager-test-{}", std::process::id()));
+    std::env::set_var(SOCKET_ENV, dir.join(SOCKET_NAME));
+    let client = async {
+        while !socket_path().exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        add_key(&[7; 32]).await.unwrap();
+        assert_eq!(fetch_key().await.as_deref(), Some(&[7; 32]));
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(fetch_key().await.is_none());
+    };
+    tokio::select! {
+        result = serve(Duration::from_millis(200)) => panic!("Agent stopped: {:?}", result),
+        _ = client => {}
+    }
+
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,1174 @@
+/*
+ * ----------------------------------------------------------------------------
+ * Project:     Personal Password Manager
+ * File:        cli.rs
+ * Description: Non-interactive subcommands for using the vault from scripts,
+ *              run instead of the TUI when the client is given arguments.
+ *
+ * Author:      RoscoeEH
+ * ---------------------------------------------------------------------------
+ */
+
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use zeroize::Zeroizing;
+
+use crate::credential::Credential;
+use crate::entry::{EntryKind, FieldType, Record};
+use crate::reference::{self, Part, Reference};
+use crate::{agent, config, crypto, ssh_agent, KEY, MASK, SERVER_ADDR};
+
+// Length of passwords made by generate and --generate when none is given
+const DEFAULT_PASSWORD_LENGTH: usize = 20;
+
+const USAGE: &str = "Usage: client [command] [options]
+
+Commands:
+  list                      List entries as title, username, and URL, tab separated
+  search <query>            List entries matching a fuzzy search, best match first
+  get <title> [--field F]   Show an entry with secrets masked, or print one field in full
+  add <title> [options]     Store a new login
+  edit <title> [options]    Change an existing entry
+  rm <title>                Move an entry to the trash
+  generate [length]         Print a random password without opening the vault
+  agent [--ttl SECS]        Run an agent that holds the vault key so later commands
+                            don't ask for the master password
+  lock                      Make the running agent forget the vault key
+  render <template>         Print a file with each {{ pm://<title>/<field> }} in it
+                            replaced by the field's value; - reads standard input
+  ssh-agent                 Serve the keys of SSH key entries to SSH clients until
+                            interrupted, without asking before each use
+  git-credential <action>   Act as a git credential helper, answering get, store,
+                            and erase from the stored logins
+  run [--env-file FILE] -- <command> [args]
+                            Run a command with pm://<title>/<field> references in the
+                            environment or env file replaced by the fields' values
+
+Fields for get --field: title, username, password, url, notes, totp, hotp,
+or the name of a kind-specific or custom field
+
+Options for add and edit:
+  --username NAME           Username of the login
+  --url URL                 URL of the login
+  --notes TEXT              Notes on the entry
+  --max-age DAYS            Days before the password is due for rotation
+  --generate LENGTH         Use a generated password
+  --password-stdin          Read the password from the first line of standard input
+  --password                Prompt for a new password (edit only; add always sets one)
+  --rename TITLE            Give the entry a new title (edit only); its previous
+                            versions stay with the old title, which goes to the trash
+
+Output:
+  --format FORMAT           text (the default) or json, for list, search, get, and
+                            generate; errors are printed as a JSON object too
+  --reveal FIELDS           Comma-separated secret fields get includes in json output,
+                            such as password,totp; secrets are left out otherwise
+
+Master password:
+  --password-fd N           Read the master password from file descriptor N
+                            instead of prompting for it or asking the agent
+
+Exit codes:
+  0  success                3  wrong master password     5  entry already exists
+  1  other failure          4  entry or field not found  6  server unreachable
+  2  invalid usage
+
+Without a command the interactive interface starts.";
+
+// Options that take a value; the others are flags
+const VALUE_OPTIONS: &[&str] = &[
+    "--field",
+    "--username",
+    "--url",
+    "--notes",
+    "--max-age",
+    "--generate",
+    "--rename",
+    "--password-fd",
+    "--format",
+    "--reveal",
+    "--ttl",
+    "--env-file",
+];
+const FLAGS: &[&str] = &["--password", "--password-stdin"];
+
+// Kinds of failure, each exiting with its own code so scripts can tell them apart.
+// Scripts depend on these codes, so they must not change.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Failure {
+    Other,
+    Usage,
+    Auth,
+    NotFound,
+    Exists,
+    Connection,
+}
+
+impl Failure {
+    fn exit_code(self) -> i32 {
+        match self {
+            Failure::Other => 1,
+            Failure::Usage => 2,
+            Failure::Auth => 3,
+            Failure::NotFound => 4,
+            Failure::Exists => 5,
+            Failure::Connection => 6,
+        }
+    }
+
+    // Name given to the failure in JSON error objects
+    fn as_str(self) -> &'static str {
+        match self {
+            Failure::Other => "error",
+            Failure::Usage => "usage",
+            Failure::Auth => "auth",
+            Failure::NotFound => "not_found",
+            Failure::Exists => "exists",
+            Failure::Connection => "connection",
+        }
+    }
+}
+
+// Error tagged with its kind of failure; errors from elsewhere count as Failure::Other
+#[derive(Debug)]
+struct CliError {
+    failure: Failure,
+    message: String,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for CliError {}
+
+fn fail(failure: Failure, message: impl Into<String>) -> Box<dyn Error> {
+    Box::new(CliError {
+        failure,
+        message: message.into(),
+    })
+}
+
+fn failure_of(e: &(dyn Error + 'static)) -> Failure {
+    e.downcast_ref::<CliError>()
+        .map_or(Failure::Other, |e| e.failure)
+}
+
+// How results are printed
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+impl Format {
+    fn parse(value: Option<&str>) -> Result<Format, Box<dyn Error>> {
+        match value {
+            None | Some("text") => Ok(Format::Text),
+            Some("json") => Ok(Format::Json),
+            Some(other) => Err(fail(
+                Failure::Usage,
+                format!("Unknown format \"{}\"; use text or json", other),
+            )),
+        }
+    }
+}
+
+// Arguments following the command
+struct Args {
+    positional: Vec<String>,
+    options: BTreeMap<String, String>,
+    flags: Vec<String>,
+    // Everything after "--", passed along untouched
+    trailing: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, Box<dyn Error>> {
+        let mut parsed = Args {
+            positional: Vec::new(),
+            options: BTreeMap::new(),
+            flags: Vec::new(),
+            trailing: Vec::new(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                parsed.trailing = args.cloned().collect();
+                break;
+            } else if VALUE_OPTIONS.contains(&arg.as_str()) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| fail(Failure::Usage, format!("{} needs a value", arg)))?;
+                parsed.options.insert(arg.clone(), value.clone());
+            } else if FLAGS.contains(&arg.as_str()) {
+                parsed.flags.push(arg.clone());
+            } else if arg.starts_with("--") {
+                return Err(fail(Failure::Usage, format!("Unknown option {}", arg)));
+            } else {
+                parsed.positional.push(arg.clone());
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    // The single positional argument a command takes, such as a title
+    fn target(&self, what: &str) -> Result<&str, Box<dyn Error>> {
+        match self.positional.as_slice() {
+            [value] => Ok(value),
+            [] => Err(fail(Failure::Usage, format!("Missing {}", what))),
+            _ => Err(fail(
+                Failure::Usage,
+                format!("Expected a single {}; quote it if it has spaces", what),
+            )),
+        }
+    }
+
+    // Field names given to --reveal
+    fn revealed(&self) -> Vec<&str> {
+        self.option("--reveal").map_or_else(Vec::new, |fields| {
+            fields.split(',').map(str::trim).collect()
+        })
+    }
+}
+
+// Runs a command given on the command line, returning the process exit code
+pub async fn run(args: Vec<String>) -> i32 {
+    // Looked up before the arguments are parsed, so that usage errors come out as JSON too
+    let options: Vec<&String> = args.iter().take_while(|arg| *arg != "--").collect();
+    let format = options
+        .windows(2)
+        .find(|pair| pair[0] == "--format")
+        .and_then(|pair| Format::parse(Some(pair[1])).ok())
+        .unwrap_or(Format::Text);
+
+    let result = execute(&args).await;
+
+    // Nothing needs the key once the command is done
+    KEY.lock();
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            let failure = failure_of(e.as_ref());
+            match format {
+                Format::Text => eprintln!("Error: {}", e),
+                Format::Json => println!(
+                    "{}",
+                    json!({
+                        "error": {
+                            "kind": failure.as_str(),
+                            "code": failure.exit_code(),
+                            "message": e.to_string(),
+                        }
+                    })
+                ),
+            }
+            failure.exit_code()
+        }
+    }
+}
+
+async fn execute(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (command, rest) = args
+        .split_first()
+        .ok_or_else(|| fail(Failure::Usage, USAGE))?;
+    let args = Args::parse(rest)?;
+    let format = Format::parse(args.option("--format"))?;
+
+    if args.option("--reveal").is_some() && (command != "get" || format != Format::Json) {
+        return Err(fail(
+            Failure::Usage,
+            "--reveal only applies to get --format json",
+        ));
+    }
+
+    // Commands that don't need the vault
+    match command.as_str() {
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        "generate" => {
+            let length = match args.positional.as_slice() {
+                [] => DEFAULT_PASSWORD_LENGTH,
+                [length] => parse_length(length)?,
+                _ => return Err(fail(Failure::Usage, "Expected a single length")),
+            };
+            let password = Zeroizing::new(crypto::generate_password(length));
+            match format {
+                Format::Text => println!("{}", *password),
+                Format::Json => print_secret_json(json!({ "password": *password })),
+            }
+            return Ok(());
+        }
+        "agent" => {
+            let ttl = match args.option("--ttl") {
+                Some(secs) => secs
+                    .parse()
+                    .map_err(|_| fail(Failure::Usage, "--ttl needs a number of seconds"))?,
+                None => config::load()?.agent_ttl_secs,
+            };
+            return agent::serve(Duration::from_secs(ttl)).await;
+        }
+        "lock" => {
+            return agent::lock()
+                .await
+                .map_err(|e| fail(Failure::Connection, e.to_string()))
+        }
+        "run" => return run_command(&args).await,
+        "render" => return render(&args).await,
+        "git-credential" => {
+            let action = args.target("git credential action")?;
+            if !["get", "store", "erase"].contains(&action) {
+                return Err(fail(
+                    Failure::Usage,
+                    format!("Unknown git credential action \"{}\"", action),
+                ));
+            }
+        }
+        "list" | "search" | "get" | "add" | "edit" | "rm" | "ssh-agent" => {}
+        _ => {
+            return Err(fail(
+                Failure::Usage,
+                format!("Unknown command \"{}\"\n\n{}", command, USAGE),
+            ))
+        }
+    }
+
+    let mut stream = connect().await?;
+    unlock(&mut stream, &args).await?;
+
+    match command.as_str() {
+        "list" => list(&mut stream, format).await,
+        "search" => search(&mut stream, &args, format).await,
+        "get" => get(&mut stream, &args, format).await,
+        "add" => add(&mut stream, &args).await,
+        "edit" => edit(&mut stream, &args).await,
+        "git-credential" => git_credential(&mut stream, &args).await,
+        "ssh-agent" => serve_ssh_keys(&mut stream).await,
+        _ => remove(&mut stream, &args).await,
+    }
+}
+
+async fn connect() -> Result<TcpStream, Box<dyn Error>> {
+    TcpStream::connect(SERVER_ADDR).await.map_err(|e| {
+        fail(
+            Failure::Connection,
+            format!("Failed to connect to the server at {}: {}", SERVER_ADDR, e),
+        )
+    })
+}
+
+// Derives the session key from the master password, read from a file descriptor
+// when one is given and prompted for otherwise
+async fn unlock(stream: &mut TcpStream, args: &Args) -> Result<(), Box<dyn Error>> {
+    // A running agent saves prompting and deriving the key, unless a password was passed in
+    if args.option("--password-fd").is_none() {
+        if let Some(key) = agent::fetch_key().await {
+            // The agent's key is no use if the vault was opened with another password since
+            if let Some(key) = crate::validate_key(key, stream, false)
+                .await
+                .map_err(check_failed)?
+            {
+                unlock_session(&key);
+                return Ok(());
+            }
+        }
+    }
+
+    let password = match args.option("--password-fd") {
+        Some(fd) => {
+            let fd = fd.parse().map_err(|_| {
+                fail(
+                    Failure::Usage,
+                    "--password-fd needs a file descriptor number",
+                )
+            })?;
+            read_fd(fd)?
+        }
+        None => Zeroizing::new(rpassword::prompt_password("Master password: ")?),
+    };
+
+    let key = crate::validate_password(&password, stream)
+        .await
+        .map_err(check_failed)?
+        .ok_or_else(|| fail(Failure::Auth, "Invalid master password"))?;
+    unlock_session(&key);
+
+    // Hand the key to the agent, if one is running, for the commands that follow
+    let _ = agent::add_key(&key).await;
+    Ok(())
+}
+
+// A master password check that couldn't be made, as opposed to a wrong password
+fn check_failed(e: Box<dyn Error>) -> Box<dyn Error> {
+    fail(
+        Failure::Connection,
+        format!("Failed to check the master password: {}", e),
+    )
+}
+
+// Keeps the validated key for the command, warning if it couldn't be kept out of swap
+fn unlock_session(key: &[u8; 32]) {
+    if let Err(e) = KEY.unlock(key) {
+        eprintln!("Warning: failed to lock the session key in memory: {}", e);
+    }
+}
+
+// Reads a secret from a file descriptor handed over by the caller. It reads through a
+// duplicate, so the caller's descriptor, which may be standard input, is left open.
+#[cfg(unix)]
+fn read_fd(fd: i32) -> Result<Zeroizing<String>, Box<dyn Error>> {
+    use std::os::unix::io::FromRawFd;
+
+    // Also checks that the descriptor is open, rather than taking over a stray number
+    let duplicate = unsafe { libc::dup(fd) };
+    if duplicate < 0 {
+        return Err(fail(
+            Failure::Usage,
+            format!(
+                "Can't read file descriptor {}: {}",
+                fd,
+                std::io::Error::last_os_error()
+            ),
+        ));
+    }
+    let mut file = unsafe { std::fs::File::from_raw_fd(duplicate) };
+    let mut secret = Zeroizing::new(String::new());
+    file.read_to_string(&mut secret)?;
+    trim_newline(&mut secret);
+    Ok(secret)
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: i32) -> Result<Zeroizing<String>, Box<dyn Error>> {
+    Err("--password-fd is only supported on Unix".into())
+}
+
+fn trim_newline(secret: &mut String) {
+    let len = secret.trim_end_matches(['\r', '\n']).len();
+    secret.truncate(len);
+}
+
+fn parse_length(length: &str) -> Result<usize, Box<dyn Error>> {
+    match length.parse() {
+        Ok(length) if length > 0 => Ok(length),
+        _ => Err(fail(Failure::Usage, "Length must be a positive number")),
+    }
+}
+
+// Fetches an entry, naming it in the error if that fails
+async fn fetch(stream: &mut TcpStream, title: &str) -> Result<Record, Box<dyn Error>> {
+    match crate::find_record(stream, title).await {
+        Ok(Some(record)) => Ok(record),
+        Ok(None) => Err(fail(
+            Failure::NotFound,
+            format!("No entry titled \"{}\"", title),
+        )),
+        Err(e) => Err(fail(
+            Failure::Other,
+            format!("Failed to get \"{}\": {}", title, e),
+        )),
+    }
+}
+
+// Prints JSON holding decrypted values, wiping the text once it is written
+fn print_secret_json(value: Value) {
+    let output = Zeroizing::new(value.to_string());
+    println!("{}", *output);
+}
+
+// What the password list knows about an entry, as JSON
+fn summary_json(item: &crate::ListItem) -> Value {
+    json!({
+        "title": item.title,
+        "kind": item.kind,
+        "username": item.user_id,
+        "url": item.url,
+        "modified": item.modified,
+        "last_used": item.last_used,
+    })
+}
+
+async fn list(stream: &mut TcpStream, format: Format) -> Result<(), Box<dyn Error>> {
+    let mut items = crate::fetch_password_list(stream).await?;
+    items.sort_by_key(|item| item.title.to_lowercase());
+
+    match format {
+        Format::Text => {
+            for item in &items {
+                println!("{}\t{}\t{}", item.title, item.user_id, item.url);
+            }
+        }
+        Format::Json => {
+            let entries: Vec<Value> = items.iter().map(summary_json).collect();
+            println!("{}", Value::Array(entries));
+        }
+    }
+    Ok(())
+}
+
+async fn search(stream: &mut TcpStream, args: &Args, format: Format) -> Result<(), Box<dyn Error>> {
+    let query = args.target("search query")?;
+    let config = config::load()?;
+    let items = crate::fetch_password_list(stream).await?;
+    let results = crate::rank_entries(&items, query, config.search_usernames);
+
+    match format {
+        Format::Text => {
+            for result in &results {
+                let item = &items[result.index];
+                println!("{}\t{}\t{}", item.title, item.user_id, item.url);
+            }
+        }
+        Format::Json => {
+            let entries: Vec<Value> = results
+                .iter()
+                .map(|result| {
+                    let mut entry = summary_json(&items[result.index]);
+                    entry["score"] = json!(result.score);
+                    entry
+                })
+                .collect();
+            println!("{}", Value::Array(entries));
+        }
+    }
+    Ok(())
+}
+
+async fn get(stream: &mut TcpStream, args: &Args, format: Format) -> Result<(), Box<dyn Error>> {
+    let title = args.target("title")?;
+    let mut record = fetch(stream, title).await?;
+
+    let field = match args.option("--field") {
+        // Generating an HOTP code saves the advanced counter first
+        Some("hotp") => Some((
+            "hotp",
+            Zeroizing::new(crate::next_hotp_code(stream, &mut record).await?),
+        )),
+        Some(name) => {
+            let value = field_value(&record, name).ok_or_else(|| {
+                fail(
+                    Failure::NotFound,
+                    format!("\"{}\" has no field \"{}\"", title, name),
+                )
+            })?;
+            Some((name, value))
+        }
+        None => None,
+    };
+
+    match (field, format) {
+        (Some((_, value)), Format::Text) => println!("{}", *value),
+        (Some((name, value)), Format::Json) => print_secret_json(json!({
+            "title": record.title,
+            "field": name,
+            "value": *value,
+        })),
+        (None, Format::Text) => print_record(&record),
+        (None, Format::Json) => print_secret_json(record_json(&record, &args.revealed())),
+    }
+
+    // Reading an entry counts as using it, but the entry has been printed by now, so
+    // failing to record that doesn't fail the command
+    if let Err(e) = crate::touch_password(stream, &mut record).await {
+        eprintln!("Warning: failed to record the use of \"{}\": {}", title, e);
+    }
+    Ok(())
+}
+
+// Value of the field a reference names, fetching each entry only once however many
+// of its fields are used
+async fn resolve(
+    stream: &mut TcpStream,
+    reference: &Reference,
+    records: &mut BTreeMap<String, Record>,
+) -> Result<Zeroizing<String>, Box<dyn Error>> {
+    if !records.contains_key(&reference.title) {
+        let mut record = fetch(stream, &reference.title).await?;
+        crate::touch_password(stream, &mut record).await?;
+        records.insert(reference.title.clone(), record);
+    }
+
+    field_value(&records[&reference.title], &reference.field).ok_or_else(|| {
+        fail(
+            Failure::NotFound,
+            format!(
+                "\"{}\" has no field \"{}\"",
+                reference.title, reference.field
+            ),
+        )
+    })
+}
+
+// Value of a named field: a common field, the current TOTP code, a kind-specific
+// field, or a custom field
+fn field_value(record: &Record, name: &str) -> Option<Zeroizing<String>> {
+    let value = match name {
+        "title" => record.title.clone(),
+        "username" | "user_id" => record.user_id.clone(),
+        "password" => record.password.clone(),
+        "url" => record.url.clone(),
+        "notes" => record.notes.clone(),
+        "totp" => record.totp.as_ref()?.code(crate::now_secs()).ok()?,
+        _ => match record.fields.get(name) {
+            Some(value) => value.clone(),
+            None => record
+                .custom_fields
+                .iter()
+                .find(|field| field.name == name)?
+                .value
+                .clone(),
+        },
+    };
+    Some(Zeroizing::new(value))
+}
+
+// An entry as JSON. Secret values (the password, the current TOTP code, and hidden
+// fields) are only included when named in reveal.
+fn record_json(record: &Record, reveal: &[&str]) -> Value {
+    let revealed = |name: &str| reveal.contains(&name);
+
+    let mut entry = json!({
+        "title": record.title,
+        "kind": record.kind,
+        "username": record.user_id,
+        "url": record.url,
+        "notes": record.notes,
+        "max_age_days": record.max_age_days,
+        "created": record.created,
+        "modified": record.modified,
+        "last_used": record.last_used,
+        "password_changed": record.password_changed,
+    });
+    if revealed("password") {
+        entry["password"] = json!(record.password);
+    }
+    if revealed("totp") {
+        if let Some(code) = record
+            .totp
+            .as_ref()
+            .and_then(|totp| totp.code(crate::now_secs()).ok())
+        {
+            entry["totp"] = json!(code);
+        }
+    }
+
+    let mut fields = Map::new();
+    for field in record.kind.schema() {
+        if let Some(value) = record.fields.get(field.name) {
+            if !field.hidden || revealed(field.name) {
+                fields.insert(field.name.to_string(), json!(value));
+            }
+        }
+    }
+    entry["fields"] = Value::Object(fields);
+
+    entry["custom_fields"] = record
+        .custom_fields
+        .iter()
+        .map(|field| {
+            let mut custom = json!({
+                "name": field.name,
+                "type": field.field_type.as_str(),
+            });
+            if field.field_type != FieldType::Hidden || revealed(&field.name) {
+                custom["value"] = json!(field.value);
+            }
+            custom
+        })
+        .collect();
+    entry
+}
+
+// Prints an entry the way the details view shows it, with hidden values masked
+fn print_record(record: &Record) {
+    println!("Title: {}", record.title);
+    println!("Kind: {}", record.kind.label());
+
+    if record.kind == EntryKind::Login {
+        println!("Username: {}", record.user_id);
+        println!("Password: {}", MASK);
+        println!("URL: {}", record.url);
+    }
+    for field in record.kind.schema() {
+        let value = record.fields.get(field.name).map_or("", String::as_str);
+        println!(
+            "{}: {}",
+            field.label,
+            if field.hidden { MASK } else { value }
+        );
+    }
+    for field in &record.custom_fields {
+        let value = if field.field_type == FieldType::Hidden {
+            MASK
+        } else {
+            &field.value
+        };
+        println!("{}: {}", field.name, value);
+    }
+    if !record.notes.is_empty() {
+        println!("Notes:\n{}", record.notes);
+    }
+    println!("Modified: {}", crate::format_timestamp(record.modified));
+}
+
+// New password for an entry from --generate or --password-stdin, if either was given
+fn entry_password(args: &Args) -> Result<Option<Zeroizing<String>>, Box<dyn Error>> {
+    if let Some(length) = args.option("--generate") {
+        let length = parse_length(length)?;
+        return Ok(Some(Zeroizing::new(crypto::generate_password(length))));
+    }
+    if args.flag("--password-stdin") {
+        let mut password = Zeroizing::new(String::new());
+        std::io::stdin().lock().read_line(&mut password)?;
+        trim_newline(&mut password);
+        return Ok(Some(password));
+    }
+    Ok(None)
+}
+
+fn prompt_entry_password(title: &str) -> Result<Zeroizing<String>, Box<dyn Error>> {
+    let prompt = format!("Password for {}: ", title);
+    Ok(Zeroizing::new(rpassword::prompt_password(prompt)?))
+}
+
+fn parse_max_age(args: &Args) -> Result<Option<u64>, Box<dyn Error>> {
+    args.option("--max-age")
+        .map(|days| {
+            days.parse()
+                .map_err(|_| fail(Failure::Usage, "--max-age needs a number of days"))
+        })
+        .transpose()
+}
+
+async fn add(stream: &mut TcpStream, args: &Args) -> Result<(), Box<dyn Error>> {
+    let title = args.target("title")?;
+    if args.flag("--password") || args.option("--rename").is_some() {
+        return Err(fail(
+            Failure::Usage,
+            "--password and --rename only apply to edit",
+        ));
+    }
+    if crate::find_record(stream, title).await?.is_some() {
+        return Err(fail(
+            Failure::Exists,
+            format!("\"{}\" already exists; use edit to change it", title),
+        ));
+    }
+
+    let password = match entry_password(args)? {
+        Some(password) => password,
+        None => prompt_entry_password(title)?,
+    };
+
+    let mut record = Record::new(EntryKind::Login, title.to_string());
+    record.user_id = args.option("--username").unwrap_or_default().to_string();
+    record.password = password.to_string();
+    record.url = args.option("--url").unwrap_or_default().to_string();
+    record.notes = args.option("--notes").unwrap_or_default().to_string();
+    record.max_age_days = parse_max_age(args)?;
+
+    crate::store_password(stream, record).await
+}
+
+async fn edit(stream: &mut TcpStream, args: &Args) -> Result<(), Box<dyn Error>> {
+    let title = args.target("title")?;
+    let mut record = fetch(stream, title).await?;
+    let mut changed = false;
+
+    if let Some(user_id) = args.option("--username") {
+        record.user_id = user_id.to_string();
+        changed = true;
+    }
+    if let Some(url) = args.option("--url") {
+        record.url = url.to_string();
+        changed = true;
+    }
+    if let Some(notes) = args.option("--notes") {
+        record.notes = notes.to_string();
+        changed = true;
+    }
+    if let Some(max_age_days) = parse_max_age(args)? {
+        record.max_age_days = Some(max_age_days);
+        changed = true;
+    }
+
+    let password = match entry_password(args)? {
+        Some(password) => Some(password),
+        None if args.flag("--password") => Some(prompt_entry_password(title)?),
+        None => None,
+    };
+    if let Some(password) = password {
+        if *password != record.password {
+            record.password_changed = crate::now_secs();
+        }
+        record.password = password.to_string();
+        changed = true;
+    }
+
+    // A renamed entry is stored under its new title, so the old one goes to the trash.
+    // Its previous versions are kept under the old title and go to the trash with it.
+    let renamed = match args.option("--rename") {
+        Some(new_title) if new_title != title => {
+            if crate::find_record(stream, new_title).await?.is_some() {
+                return Err(fail(
+                    Failure::Exists,
+                    format!("\"{}\" already exists", new_title),
+                ));
+            }
+            let old_title_hash = record.title_hash();
+            record.title = new_title.to_string();
+            changed = true;
+            Some(old_title_hash)
+        }
+        _ => None,
+    };
+
+    if !changed {
+        return Err(fail(
+            Failure::Usage,
+            "Nothing to change; see --help for the options edit takes",
+        ));
+    }
+
+    // The entry was just fetched, so it already has the times to keep, even when renamed
+    let new_title = record.title.clone();
+    crate::store_record(stream, record).await?;
+    if let Some(old_title_hash) = renamed {
+        crate::request(stream, 5, &old_title_hash)
+            .await
+            .map_err(|e| {
+                format!(
+                    "Stored \"{}\", but failed to move \"{}\" to the trash: {}",
+                    new_title, title, e
+                )
+            })?;
+    }
+    Ok(())
+}
+
+// Answers git's credential helper protocol: the credential git is after comes in on
+// standard input, and for get the matching username and password go out on standard
+// output. Nothing is printed when no login matches, so git falls back to prompting.
+async fn git_credential(stream: &mut TcpStream, args: &Args) -> Result<(), Box<dyn Error>> {
+    let action = args.target("git credential action")?;
+    let credential = Credential::read(std::io::stdin().lock())?;
+    if credential.host.is_empty() {
+        return Ok(());
+    }
+
+    // Logins for the credential, most recently used first
+    let mut items: Vec<crate::ListItem> = crate::fetch_password_list(stream)
+        .await?
+        .into_iter()
+        .filter(|item| {
+            item.kind == EntryKind::Login && credential.matches(&item.url, &item.user_id)
+        })
+        .collect();
+    items.sort_by_key(|item| std::cmp::Reverse(item.last_used));
+
+    match action {
+        "get" => {
+            let Some(item) = items.first() else {
+                return Ok(());
+            };
+            let mut record = fetch(stream, &item.title).await?;
+            let output = Zeroizing::new(format!(
+                "username={}\npassword={}",
+                record.user_id, record.password
+            ));
+            println!("{}", *output);
+            crate::touch_password(stream, &mut record).await
+        }
+        // Git saves a credential that worked; keep the login up to date or add one
+        "store" => {
+            if credential.username.is_empty() || credential.password.is_empty() {
+                return Ok(());
+            }
+            let mut record = match items.first() {
+                Some(item) => fetch(stream, &item.title).await?,
+                None => {
+                    let title = new_credential_title(stream, &credential).await?;
+                    let mut record = Record::new(EntryKind::Login, title);
+                    record.user_id = credential.username.clone();
+                    record.url = credential.url();
+                    record
+                }
+            };
+            if record.password == *credential.password {
+                return Ok(());
+            }
+            record.password = credential.password.to_string();
+            crate::store_password(stream, record).await
+        }
+        // Git erases a credential that was rejected. Only logins still holding the
+        // rejected password are moved to the trash, so one that was since changed
+        // or that git never supplied is left alone.
+        _ => {
+            if credential.password.is_empty() {
+                return Ok(());
+            }
+            for item in &items {
+                let record = fetch(stream, &item.title).await?;
+                if record.password == *credential.password {
+                    crate::request(stream, 5, &record.title_hash()).await?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+// Title for a login saved by git: the host, or the username and host if the host
+// is already taken
+async fn new_credential_title(
+    stream: &mut TcpStream,
+    credential: &Credential,
+) -> Result<String, Box<dyn Error>> {
+    let candidates = [
+        credential.host.clone(),
+        format!("{}@{}", credential.username, credential.host),
+    ];
+    for title in candidates {
+        if crate::find_record(stream, &title).await?.is_none() {
+            return Ok(title);
+        }
+    }
+    Err(fail(
+        Failure::Exists,
+        format!(
+            "\"{}@{}\" already exists",
+            credential.username, credential.host
+        ),
+    ))
+}
+
+// Runs a command with the variables of an env file added to its environment, and
+// with every pm://<title>/<field> value there or in the current environment replaced
+// by the field it names. The vault is only opened if there is something to look up.
+async fn run_command(args: &Args) -> Result<(), Box<dyn Error>> {
+    if !args.positional.is_empty() {
+        return Err(fail(Failure::Usage, "Put the command to run after --"));
+    }
+    let (program, program_args) = args
+        .trailing
+        .split_first()
+        .ok_or_else(|| fail(Failure::Usage, "Missing command to run after --"))?;
+
+    // References already in the environment, overridden by anything in the env file
+    let mut variables: BTreeMap<String, Zeroizing<String>> = BTreeMap::new();
+    for (name, value) in std::env::vars_os() {
+        if let (Ok(name), Ok(value)) = (name.into_string(), value.into_string()) {
+            if value.starts_with("pm://") {
+                variables.insert(name, Zeroizing::new(value));
+            }
+        }
+    }
+    if let Some(path) = args.option("--env-file") {
+        let contents = Zeroizing::new(
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?,
+        );
+        variables.extend(reference::parse_env_file(&contents)?);
+    }
+
+    let mut references = Vec::new();
+    for (name, value) in &variables {
+        let reference = Reference::parse(value)
+            .map_err(|e| fail(Failure::Usage, format!("{}: {}", name, e)))?;
+        if let Some(reference) = reference {
+            references.push((name.clone(), reference));
+        }
+    }
+
+    if !references.is_empty() {
+        let mut stream = connect().await?;
+        unlock(&mut stream, args).await?;
+
+        let mut records = BTreeMap::new();
+        for (name, reference) in &references {
+            let value = resolve(&mut stream, reference, &mut records).await?;
+            variables.insert(name.clone(), value);
+        }
+    }
+
+    let mut command = std::process::Command::new(program);
+    command
+        .args(program_args)
+        .envs(variables.iter().map(|(name, value)| (name, value.as_str())));
+
+    // The child gets the secrets it needs, not the key
+    KEY.lock();
+    exec(command, program)
+}
+
+// Prints a template with its references filled in. Nothing is printed unless every
+// reference resolves, so a missing entry can't leave a half-written file behind.
+async fn render(args: &Args) -> Result<(), Box<dyn Error>> {
+    let path = args.target("template")?;
+    let template = Zeroizing::new(if path == "-" {
+        let mut template = String::new();
+        std::io::stdin().read_to_string(&mut template)?;
+        template
+    } else {
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?
+    });
+    let parts = reference::parse_template(&template)
+        .map_err(|e| fail(Failure::Usage, format!("{}: {}", path, e)))?;
+
+    let mut values = Vec::new();
+    if parts.iter().any(|part| matches!(part, Part::Reference(_))) {
+        let mut stream = connect().await?;
+        unlock(&mut stream, args).await?;
+
+        let mut records = BTreeMap::new();
+        for part in &parts {
+            if let Part::Reference(reference) = part {
+                values.push(resolve(&mut stream, reference, &mut records).await?);
+            }
+        }
+    }
+
+    // Sized up front so the secrets aren't left behind in a smaller buffer
+    let length = template.len() + values.iter().map(|value| value.len()).sum::<usize>();
+    let mut output = Zeroizing::new(String::with_capacity(length));
+    let mut values = values.iter();
+    for part in &parts {
+        match part {
+            Part::Text(text) => output.push_str(text),
+            Part::Reference(_) => output.push_str(values.next().map_or("", |value| value)),
+        }
+    }
+
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(output.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+// Replaces this process with the command, so its exit status is the command's own
+#[cfg(unix)]
+fn exec(mut command: std::process::Command, program: &str) -> Result<(), Box<dyn Error>> {
+    use std::os::unix::process::CommandExt;
+
+    // Only returns if the command couldn't be started
+    let e = command.exec();
+    Err(format!("Failed to run {}: {}", program, e).into())
+}
+
+#[cfg(not(unix))]
+fn exec(mut command: std::process::Command, program: &str) -> Result<(), Box<dyn Error>> {
+    let status = command
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+// Serves the keys of SSH key entries until interrupted. Keys are loaded once, and
+// every use is allowed; the interface can ask before each use instead.
+async fn serve_ssh_keys(stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+    let (identities, failures) = ssh_agent::load_identities(stream).await?;
+    for failure in &failures {
+        eprintln!("Skipping {}", failure);
+    }
+
+    let keyring = Arc::new(ssh_agent::Keyring::default());
+    keyring.replace(identities);
+    ssh_agent::start(keyring.clone(), false).await?;
+
+    // The keys are all that's needed from here on
+    KEY.lock();
+    let path = ssh_agent::socket_path();
+    println!("SSH agent listening on {}", path.display());
+    println!("export SSH_AUTH_SOCK={}", path.display());
+
+    let stopped = shutdown_signal().await;
+    keyring.clear();
+    ssh_agent::stop();
+    stopped
+}
+
+// Waits for Ctrl-C or, on Unix, a request to terminate
+async fn shutdown_signal() -> Result<(), Box<dyn Error>> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            interrupted = tokio::signal::ctrl_c() => interrupted?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+async fn remove(stream: &mut TcpStream, args: &Args) -> Result<(), Box<dyn Error>> {
+    let title = args.target("title")?;
+    let record = fetch(stream, title).await?;
+
+    // Send delete request (type 5), which moves the entry to the trash
+    crate::request(stream, 5, &record.title_hash()).await?;
+    Ok(())
+}
+
+#[test]
+fn argument_parsing() {
+    let args: Vec<String> = ["GitHub", "--field", "password", "--password-stdin"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let parsed = Args::parse(&args).expect("Arguments should parse");
+    assert_eq!(parsed.target("title").ok(), Some("GitHub"));
+    assert_eq!(parsed.option("--field"), Some("password"));
+    assert!(parsed.flag("--password-stdin"));
+    assert!(!parsed.flag("--password"));
+
+    let args: Vec<String> = ["--env-file", ".env", "--", "env", "--bogus"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let parsed = Args::parse(&args).expect("Arguments after -- should be left alone");
+    assert_eq!(parsed.option("--env-file"), Some(".env"));
+    assert_eq!(parsed.trailing, ["env", "--bogus"]);
+
+    assert!(Args::parse(&["--field".to_string()]).is_err());
+    let error = Args::parse(&["--bogus".to_string()]).err().unwrap();
+    assert_eq!(failure_of(error.as_ref()), Failure::Usage);
+    let other: Box<dyn Error> = "other".into();
+    assert_eq!(failure_of(other.as_ref()), Failure::Other);
+}
+
+#[test]
+fn json_reveals_only_named_secrets() {
+    let mut record = Record::new(EntryKind::Login, "GitHub".to_string());
+    record.user_id = "octocat".to_string();
+    record.password = "hunter2".to_string();
+
+    let hidden = record_json(&record, &[]);
+    assert_eq!(hidden["username"], "octocat");
+    assert!(hidden.get("password").is_none());
+
+    let revealed = record_json(&record, &["password"]);
+    assert_eq!(revealed["password"], "hunter2");
+}
@@ -0,0 +1,72 @@
+/*
+ * ----------------------------------------------------------------------------
+ * Project:     Personal Password Manager
+ * File:        config.rs
+ * Description: Client settings loaded from a JSON file, falling back to
+ *              defaults for anything that isn't set.
+ *
+ * Author:      RoscoeEH
+ * ---------------------------------------------------------------------------
+ */
+
+use serde::Deserialize;
+use std::error::Error;
+use std::path::PathBuf;
+
+// Settings file name, looked up in the home directory unless PM_CONFIG is set
+const CONFIG_FILE: &str = ".password_manager.json";
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Days before a password is due for rotation, unless the entry sets its own
+    pub max_password_age_days: u64,
+    // Whether search also matches usernames, not just titles and URLs
+    pub search_usernames: bool,
+    // Seconds before a copied value is cleared from the clipboard, 0 to leave it there
+    pub clipboard_clear_secs: u64,
+    // Seconds without a key press before the vault locks, 0 to never lock
+    pub idle_lock_secs: u64,
+    // Seconds the agent holds the key after it is handed over, 0 to hold it until locked
+    pub agent_ttl_secs: u64,
+    // Whether the interface serves SSH key entries to SSH clients
+    pub ssh_agent: bool,
+    // Whether each use of an SSH key has to be allowed in the interface
+    pub ssh_agent_confirm: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_password_age_days: 90,
+            search_usernames: true,
+            clipboard_clear_secs: 30,
+            idle_lock_secs: 300,
+            agent_ttl_secs: 900,
+            ssh_agent: false,
+            ssh_agent_confirm: true,
+        }
+    }
+}
+
+// Location of the settings file
+fn config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("PM_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(CONFIG_FILE))
+}
+
+// Loads the settings file, using defaults when it doesn't exist
+pub fn load() -> Result<Config, Box<dyn Error>> {
+    match config_path() {
+        Some(path) if path.exists() => {
+            let contents = std::fs::read(&path)?;
+            serde_json::from_slice(&contents)
+                .map_err(|e| format!("Invalid config file {}: {}", path.display(), e).into())
+        }
+        _ => Ok(Config::default()),
+    }
+}
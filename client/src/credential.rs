@@ -0,0 +1,119 @@
+/*
+ * ----------------------------------------------------------------------------
+ * Project:     Personal Password Manager
+ * File:        credential.rs
+ * Description: Reads credential descriptions in git's credential helper format
+ *              and matches them against stored logins.
+ *
+ * Author:      RoscoeEH
+ * ---------------------------------------------------------------------------
+ */
+
+use std::error::Error;
+use std::io::BufRead;
+use zeroize::Zeroizing;
+
+// A credential as git describes it, one key=value attribute per line
+#[derive(Default)]
+pub struct Credential {
+    pub protocol: String,
+    pub host: String,
+    pub username: String,
+    pub password: Zeroizing<String>,
+}
+
+impl Credential {
+    // Reads attributes up to a blank line or the end of input. Attributes this
+    // client doesn't use, such as path or capability[], are skipped.
+    pub fn read(input: impl BufRead) -> Result<Credential, Box<dyn Error>> {
+        let mut credential = Credential::default();
+
+        for line in input.lines() {
+            let line = Zeroizing::new(line?);
+            if line.is_empty() {
+                break;
+            }
+            // The line isn't echoed in the error since it may hold a password
+            let (key, value) = line
+                .split_once('=')
+                .ok_or("Invalid credential attribute, expected key=value")?;
+
+            match key {
+                "protocol" => credential.protocol = value.to_string(),
+                "host" => credential.host = value.to_string(),
+                "username" => credential.username = value.to_string(),
+                "password" => credential.password = Zeroizing::new(value.to_string()),
+                // A URL stands in for the attributes it contains
+                "url" => {
+                    let (scheme, user, host) = split_url(value);
+                    credential.protocol = scheme.unwrap_or_default().to_string();
+                    credential.host = host.to_string();
+                    if let Some(user) = user {
+                        credential.username = user.to_string();
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(credential)
+    }
+
+    // Whether a login with this URL and username is for this credential. The host
+    // must match, the protocol too if the URL has one, and the username if git
+    // asked for a particular one.
+    pub fn matches(&self, url: &str, user_id: &str) -> bool {
+        let (scheme, _, host) = split_url(url);
+
+        !host.is_empty()
+            && host.eq_ignore_ascii_case(&self.host)
+            && scheme.is_none_or(|scheme| scheme.eq_ignore_ascii_case(&self.protocol))
+            && (self.username.is_empty() || self.username == user_id)
+    }
+
+    // URL stored with logins saved for this credential
+    pub fn url(&self) -> String {
+        if self.protocol.is_empty() {
+            self.host.clone()
+        } else {
+            format!("{}://{}", self.protocol, self.host)
+        }
+    }
+}
+
+// Splits a URL into its scheme, user, and host (with any port), leaving out the
+// path. Stored URLs may leave out the scheme, as in "github.com/login".
+fn split_url(url: &str) -> (Option<&str>, Option<&str>, &str) {
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (Some(scheme), rest),
+        None => (None, url),
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    match authority.rsplit_once('@') {
+        // Any password in the user info is dropped
+        Some((user, host)) => (scheme, user.split(':').next(), host),
+        None => (scheme, None, authority),
+    }
+}
+
+#[test]
+fn credential_matching() {
+    let input = "protocol=https\nhost=github.com\ncapability[]=authtype\n\nignored=1\n";
+    let credential = Credential::read(input.as_bytes()).expect("Credential should parse");
+    assert_eq!(credential.protocol, "https");
+    assert_eq!(credential.host, "github.com");
+    assert_eq!(credential.url(), "https://github.com");
+
+    assert!(credential.matches("https://github.com/login", "octocat"));
+    assert!(credential.matches("GitHub.com", "octocat"));
+    assert!(!credential.matches("http://github.com", "octocat"));
+    assert!(!credential.matches("https://gitlab.com", "octocat"));
+    assert!(!credential.matches("", "octocat"));
+
+    let input = "url=https://octocat@github.com/org/repo.git\n";
+    let credential = Credential::read(input.as_bytes()).expect("Credential should parse");
+    assert_eq!(credential.username, "octocat");
+    assert!(credential.matches("https://github.com", "octocat"));
+    assert!(!credential.matches("https://github.com", "someone"));
+
+    assert!(Credential::read("password".as_bytes()).is_err());
+}
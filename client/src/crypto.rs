@@ -11,33 +11,33 @@
 
 // Since this is just used as a library for the client the functions will never be called
 #[allow(dead_code)]
-use pbkdf2::pbkdf2_hmac_array;
+use pbkdf2::pbkdf2_hmac;
 use sha2::{Digest, Sha256};
-use std::convert::TryFrom;
+use zeroize::Zeroizing;
 
 use rand::{thread_rng, Rng};
 
 use aes_gcm::{
     aead::{Aead, AeadCore, KeyInit},
-    Aes256Gcm, Key, Nonce,
+    Aes256Gcm, Nonce,
 };
 
 // Values taken from Docs
 const PBKDF_ITERATIONS: u32 = 600_000;
 const SALT: &[u8] = b"%&@/";
 
-// Takes in user password and generates 256-bit key with PBKDF
-pub fn key_derivation(password: String) -> [u8; 32] {
-    // Runs PBKDF
-    let key: [u8; 32] =
-        pbkdf2_hmac_array::<Sha256, 32>(password.as_bytes(), SALT, PBKDF_ITERATIONS);
+// Takes in user password and generates 256-bit key with PBKDF, wiped when dropped
+pub fn key_derivation(password: &str) -> Zeroizing<[u8; 32]> {
+    // Runs PBKDF straight into the key's own buffer so no other copy is left behind
+    let mut key = Zeroizing::new([0; 32]);
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), SALT, PBKDF_ITERATIONS, &mut *key);
 
     key
 }
 
 #[test]
 fn pbkdf2_kat() {
-    let password = "test_password".to_string();
+    let password = "test_password";
 
     let expected_key: [u8; 32] = [
         0x57, 0x49, 0x0a, 0xf8, 0x65, 0x9b, 0x91, 0x45, 0xca, 0x91, 0x2d, 0x1e, 0x4b, 0xa2, 0xb3,
@@ -45,18 +45,63 @@ fn pbkdf2_kat() {
         0x22, 0xff,
     ];
     let derived_key = key_derivation(password);
-    assert_eq!(derived_key, expected_key, "PBKDF2 KAT Failed");
+    assert_eq!(*derived_key, expected_key, "PBKDF2 KAT Failed");
 }
 
-// Uses AES256gcm authenticated encryption to encrypt a string
-pub fn encrypt(message: String, key: [u8; 32]) -> Vec<u8> {
-    let use_key = Key::<Aes256Gcm>::try_from(key).expect("Invalid key length");
-    let cipher = Aes256Gcm::new(&use_key);
+// Smallest padded plaintext, big enough to hide the length of typical titles and passwords
+const MIN_PADDED_LEN: usize = 256;
+
+// Uses AES256gcm authenticated encryption to encrypt a string, wiping the plaintext
+pub fn encrypt(message: String, key: &[u8; 32]) -> Vec<u8> {
+    let message = Zeroizing::new(message);
+    encrypt_bytes(message.as_bytes(), key)
+}
+
+// Encrypts a string after padding it, so only its size bucket shows in the ciphertext
+pub fn encrypt_padded(message: String, key: &[u8; 32]) -> Vec<u8> {
+    let message = Zeroizing::new(message);
+    let padded = Zeroizing::new(pad(message.as_bytes()));
+    encrypt_bytes(&padded, key)
+}
+
+// Decrypts a padded ciphertext and strips the padding
+pub fn decrypt_padded(ciphertext: Vec<u8>, key: &[u8; 32]) -> Vec<u8> {
+    unpad(decrypt(ciphertext, key))
+}
+
+// Decrypts a padded ciphertext like decrypt_padded, returning an error instead of panicking
+pub fn try_decrypt_padded(ciphertext: Vec<u8>, key: &[u8; 32]) -> Result<Vec<u8>, String> {
+    try_decrypt(ciphertext, key).map(unpad)
+}
+
+// Pads a message with a 0x80 marker and zeros up to the next power of two
+pub fn pad(message: &[u8]) -> Vec<u8> {
+    let padded_len = (message.len() + 1).next_power_of_two().max(MIN_PADDED_LEN);
+
+    let mut padded = Vec::with_capacity(padded_len);
+    padded.extend_from_slice(message);
+    padded.push(0x80);
+    padded.resize(padded_len, 0);
+    padded
+}
+
+// Strips padding added by pad, leaving messages that were never padded unchanged
+pub fn unpad(mut padded: Vec<u8>) -> Vec<u8> {
+    let Some(marker) = padded.iter().rposition(|&byte| byte != 0) else {
+        return padded;
+    };
+    if padded[marker] == 0x80 {
+        padded.truncate(marker);
+    }
+    padded
+}
+
+// Encrypts raw bytes with AES256gcm, prefixing the nonce
+fn encrypt_bytes(message: &[u8], key: &[u8; 32]) -> Vec<u8> {
+    let cipher = Aes256Gcm::new_from_slice(key).expect("Invalid key length");
     let nonce = Aes256Gcm::generate_nonce().expect("Failed to generate nonce");
 
-    let ciphertext = cipher
-        .encrypt(&nonce, message.as_bytes().as_ref())
-        .expect("Encryption Error");
+    let ciphertext = cipher.encrypt(&nonce, message).expect("Encryption Error");
 
     // Combine nonce and ciphertext into a single Vec
     let mut encrypted = nonce.to_vec();
@@ -65,22 +110,26 @@ pub fn encrypt(message: String, key: [u8; 32]) -> Vec<u8> {
 }
 
 // Decryptes AES256gcm encryption and returns a Vec<u8>
-pub fn decrypt(ciphertext: Vec<u8>, aes_key: [u8; 32]) -> Vec<u8> {
+pub fn decrypt(ciphertext: Vec<u8>, aes_key: &[u8; 32]) -> Vec<u8> {
+    try_decrypt(ciphertext, aes_key).unwrap_or_else(|e| panic!("{}", e))
+}
+
+// Decrypts like decrypt, but returns an error for a wrong key or damaged ciphertext
+pub fn try_decrypt(ciphertext: Vec<u8>, aes_key: &[u8; 32]) -> Result<Vec<u8>, String> {
     if ciphertext.len() < 12 {
         // Check if we have enough bytes for the nonce
-        panic!("Ciphertext too short");
+        return Err("Ciphertext too short".to_string());
     }
 
     // Split the input into nonce and ciphertext
     let (nonce_slice, encrypted_data) = ciphertext.split_at(12);
 
-    let key = Key::<Aes256Gcm>::try_from(aes_key).expect("Invalid key length");
-    let cipher = Aes256Gcm::new(&key);
+    let cipher = Aes256Gcm::new_from_slice(aes_key).expect("Invalid key length");
 
     let nonce = Nonce::from_slice(nonce_slice);
     cipher
         .decrypt(nonce, encrypted_data)
-        .expect("Decryption Error")
+        .map_err(|_| "Decryption Error".to_string())
 }
 
 #[test]
@@ -93,13 +142,13 @@ fn aes256_kat() {
     let plaintext = "This is a test message".to_string();
 
     // Encrypt the plaintext
-    let encrypted = encrypt(plaintext.clone(), key);
+    let encrypted = encrypt(plaintext.clone(), &key);
 
     // Ensure that encryption produced output longer than just the nonce
     assert!(encrypted.len() > 12, "AES-256 KAT Failed - No ciphertext");
 
     // Decrypt back and compare with the original plaintext
-    let decrypted = decrypt(encrypted, key);
+    let decrypted = decrypt(encrypted, &key);
     assert_eq!(
         decrypted,
         plaintext.as_bytes(),
@@ -107,6 +156,37 @@ fn aes256_kat() {
     );
 }
 
+#[test]
+fn padding_hides_length() {
+    let key = [0x42; 32];
+
+    let short = encrypt_padded("hunter2".to_string(), &key);
+    let long = encrypt_padded("correct horse battery staple".to_string(), &key);
+    assert_eq!(short.len(), long.len(), "Padded ciphertext lengths differ");
+
+    let decrypted = decrypt_padded(short, &key);
+    assert_eq!(decrypted, b"hunter2", "Padding did not round trip");
+
+    // Longer messages move up to the next bucket
+    let bucketed = encrypt_padded("x".repeat(MIN_PADDED_LEN), &key);
+    assert_eq!(bucketed.len(), long.len() + MIN_PADDED_LEN);
+
+    // Plaintexts stored before padding are left alone
+    assert_eq!(unpad(b"{\"a\":1}".to_vec()), b"{\"a\":1}");
+}
+
+#[test]
+fn wrong_key_fails_to_decrypt() {
+    let encrypted = encrypt_padded("hunter2".to_string(), &[0x42; 32]);
+
+    assert!(try_decrypt_padded(encrypted.clone(), &[0x24; 32]).is_err());
+    assert!(try_decrypt(encrypted[..11].to_vec(), &[0x42; 32]).is_err());
+    assert_eq!(
+        try_decrypt_padded(encrypted, &[0x42; 32]).unwrap(),
+        b"hunter2"
+    );
+}
+
 // Enum to adapt hash to work for both a string and a [u8;32]
 pub enum HashInputType {
     Text(String),
@@ -0,0 +1,332 @@
+/*
+ * ----------------------------------------------------------------------------
+ * Project:     Personal Password Manager
+ * File:        entry.rs
+ * Description: Kinds of entries the client can store, the fields each kind
+ *              holds, and the versioned record they are serialized into.
+ *
+ * Author:      RoscoeEH
+ * ---------------------------------------------------------------------------
+ */
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use zeroize::Zeroize;
+
+use crate::crypto;
+use crate::totp::{Hotp, Totp};
+
+// Current version of the record format
+pub const RECORD_VERSION: u32 = 1;
+
+// Kinds of entries that can be stored
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    #[default]
+    Login,
+    #[serde(rename = "note")]
+    SecureNote,
+    Card,
+    Identity,
+    SshKey,
+    ApiToken,
+}
+
+// A field that belongs to an entry kind
+pub struct SchemaField {
+    pub name: &'static str,
+    pub label: &'static str,
+    pub hidden: bool,
+    pub multiline: bool,
+}
+
+const fn field(name: &'static str, label: &'static str) -> SchemaField {
+    SchemaField {
+        name,
+        label,
+        hidden: false,
+        multiline: false,
+    }
+}
+
+const fn hidden(name: &'static str, label: &'static str) -> SchemaField {
+    SchemaField {
+        name,
+        label,
+        hidden: true,
+        multiline: false,
+    }
+}
+
+const fn multiline(name: &'static str, label: &'static str, hidden: bool) -> SchemaField {
+    SchemaField {
+        name,
+        label,
+        hidden,
+        multiline: true,
+    }
+}
+
+const CARD_SCHEMA: &[SchemaField] = &[
+    field("cardholder", "Cardholder name"),
+    hidden("number", "Card number"),
+    field("expiry", "Expiry (MM/YY)"),
+    hidden("cvv", "CVV"),
+];
+
+const IDENTITY_SCHEMA: &[SchemaField] = &[
+    field("full_name", "Full name"),
+    multiline("address", "Address", false),
+    field("phone", "Phone"),
+    field("email", "Email"),
+];
+
+const SSH_KEY_SCHEMA: &[SchemaField] = &[
+    multiline("private_key", "Private key", true),
+    multiline("public_key", "Public key", false),
+    hidden("passphrase", "Passphrase"),
+];
+
+const API_TOKEN_SCHEMA: &[SchemaField] = &[hidden("token", "Token"), field("expires", "Expires")];
+
+impl EntryKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EntryKind::Login => "login",
+            EntryKind::SecureNote => "note",
+            EntryKind::Card => "card",
+            EntryKind::Identity => "identity",
+            EntryKind::SshKey => "ssh_key",
+            EntryKind::ApiToken => "api_token",
+        }
+    }
+
+    pub fn parse(value: &str) -> EntryKind {
+        match value {
+            "note" => EntryKind::SecureNote,
+            "card" => EntryKind::Card,
+            "identity" => EntryKind::Identity,
+            "ssh_key" => EntryKind::SshKey,
+            "api_token" => EntryKind::ApiToken,
+            _ => EntryKind::Login,
+        }
+    }
+
+    // Name shown as the heading of an entry's details
+    pub fn label(self) -> &'static str {
+        match self {
+            EntryKind::Login => "Password",
+            EntryKind::SecureNote => "Secure Note",
+            EntryKind::Card => "Payment Card",
+            EntryKind::Identity => "Identity",
+            EntryKind::SshKey => "SSH Key",
+            EntryKind::ApiToken => "API Token",
+        }
+    }
+
+    // Kind-specific fields; logins and notes only use the common ones
+    pub fn schema(self) -> &'static [SchemaField] {
+        match self {
+            EntryKind::Login | EntryKind::SecureNote => &[],
+            EntryKind::Card => CARD_SCHEMA,
+            EntryKind::Identity => IDENTITY_SCHEMA,
+            EntryKind::SshKey => SSH_KEY_SCHEMA,
+            EntryKind::ApiToken => API_TOKEN_SCHEMA,
+        }
+    }
+
+    // Kind-specific field copied by 'c-p' for kinds other than logins
+    pub fn primary_secret(self) -> Option<&'static str> {
+        match self {
+            EntryKind::Card => Some("number"),
+            EntryKind::SshKey => Some("private_key"),
+            EntryKind::ApiToken => Some("token"),
+            _ => None,
+        }
+    }
+}
+
+// Types of custom fields, which decide how a field is entered and shown
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Text,
+    Hidden,
+    Url,
+    Number,
+}
+
+impl FieldType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FieldType::Text => "text",
+            FieldType::Hidden => "hidden",
+            FieldType::Url => "url",
+            FieldType::Number => "number",
+        }
+    }
+
+    pub fn parse(value: &str) -> FieldType {
+        match value {
+            "hidden" => FieldType::Hidden,
+            "url" => FieldType::Url,
+            "number" => FieldType::Number,
+            _ => FieldType::Text,
+        }
+    }
+}
+
+// Extra named field on an entry
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CustomField {
+    pub name: String,
+    pub field_type: FieldType,
+    pub value: String,
+}
+
+impl Drop for CustomField {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+// Everything about an entry, serialized and encrypted as a single payload
+// Fields default one by one when missing, since wiping on drop rules out
+// filling them from a whole default record
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Record {
+    #[serde(default = "current_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub kind: EntryKind,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub user_id: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub notes: String,
+    // Maximum password age in days, None to use the configured default
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+    #[serde(default)]
+    pub custom_fields: Vec<CustomField>,
+    // Authenticator settings for two-factor codes
+    #[serde(default)]
+    pub totp: Option<Totp>,
+    #[serde(default)]
+    pub hotp: Option<Hotp>,
+    // Kind-specific fields, keyed by schema field name
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+    // Unix timestamps, 0 for entries stored before they were tracked
+    #[serde(default)]
+    pub created: u64,
+    #[serde(default)]
+    pub modified: u64,
+    #[serde(default)]
+    pub last_used: u64,
+    #[serde(default)]
+    pub password_changed: u64,
+}
+
+fn current_version() -> u32 {
+    RECORD_VERSION
+}
+
+impl Default for Record {
+    fn default() -> Self {
+        Record::new(EntryKind::Login, String::new())
+    }
+}
+
+// The plaintext secrets are overwritten when a record is dropped
+impl Drop for Record {
+    fn drop(&mut self) {
+        self.password.zeroize();
+        self.notes.zeroize();
+        for value in self.fields.values_mut() {
+            value.zeroize();
+        }
+    }
+}
+
+impl Record {
+    // Empty record of a kind, to fill in field by field
+    pub fn new(kind: EntryKind, title: String) -> Record {
+        Record {
+            version: RECORD_VERSION,
+            kind,
+            title,
+            user_id: String::new(),
+            password: String::new(),
+            url: String::new(),
+            notes: String::new(),
+            max_age_days: None,
+            custom_fields: Vec::new(),
+            totp: None,
+            hotp: None,
+            fields: BTreeMap::new(),
+            created: 0,
+            modified: 0,
+            last_used: 0,
+            password_changed: 0,
+        }
+    }
+
+    // Hash of the title, which the server stores the entry under
+    pub fn title_hash(&self) -> [u8; 32] {
+        crypto::hash(crypto::HashInputType::Text(self.title.clone()))
+    }
+}
+
+// Checks a value entered for one of a kind's fields
+pub fn validate(kind: EntryKind, name: &str, value: &str) -> Result<(), String> {
+    let digits = |value: &str| !value.is_empty() && value.chars().all(|c| c.is_ascii_digit());
+
+    let valid = match (kind, name) {
+        (EntryKind::Card, "number") => {
+            let number: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+            digits(&number) && (12..=19).contains(&number.len())
+        }
+        (EntryKind::Card, "expiry") => match value.split_once('/') {
+            Some((month, year)) => {
+                digits(year)
+                    && matches!(year.len(), 2 | 4)
+                    && digits(month)
+                    && matches!(month.parse::<u32>(), Ok(1..=12))
+            }
+            None => false,
+        },
+        (EntryKind::Card, "cvv") => digits(value) && (3..=4).contains(&value.len()),
+        (EntryKind::SshKey, "private_key") => value.trim_start().starts_with("-----BEGIN"),
+        (EntryKind::ApiToken, "token") => !value.is_empty(),
+        _ => true,
+    };
+    if valid {
+        return Ok(());
+    }
+
+    Err(match name {
+        "number" => "Card number must be 12 to 19 digits",
+        "expiry" => "Expiry must be in MM/YY format",
+        "cvv" => "CVV must be 3 or 4 digits",
+        "private_key" => "Private key must be in PEM or OpenSSH format",
+        _ => "Token can't be empty",
+    }
+    .to_string())
+}
+
+#[test]
+fn card_validation() {
+    assert!(validate(EntryKind::Card, "number", "4111 1111 1111 1111").is_ok());
+    assert!(validate(EntryKind::Card, "number", "4111-1111").is_err());
+    assert!(validate(EntryKind::Card, "expiry", "09/27").is_ok());
+    assert!(validate(EntryKind::Card, "expiry", "13/27").is_err());
+    assert!(validate(EntryKind::Card, "cvv", "123").is_ok());
+    assert!(validate(EntryKind::Card, "cvv", "12a").is_err());
+}
@@ -0,0 +1,116 @@
+/*
+ * ----------------------------------------------------------------------------
+ * Project:     Personal Password Manager
+ * File:        legacy.rs
+ * Description: Reads entries stored before records were encrypted as a single
+ *              payload, when every field had its own ciphertext.
+ *
+ * Author:      RoscoeEH
+ * ---------------------------------------------------------------------------
+ */
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use zeroize::Zeroizing;
+
+use crate::crypto;
+use crate::entry::{CustomField, EntryKind, FieldType, Record, RECORD_VERSION};
+use crate::KEY;
+
+// Entry with each field encrypted separately
+#[derive(Deserialize)]
+pub struct LegacyPasswordInfo {
+    title: Vec<u8>,
+    user_id: Vec<u8>,
+    password: Vec<u8>,
+    url: Vec<u8>,
+    #[serde(default)]
+    created: Vec<u8>,
+    #[serde(default)]
+    modified: Vec<u8>,
+    #[serde(default)]
+    last_used: Vec<u8>,
+    #[serde(default)]
+    password_changed: Vec<u8>,
+    #[serde(default)]
+    max_age_days: Vec<u8>,
+    #[serde(default)]
+    notes: Vec<u8>,
+    #[serde(default)]
+    kind: Vec<u8>,
+    #[serde(default)]
+    custom_fields: Vec<LegacyCustomField>,
+    #[serde(default)]
+    payload: Vec<u8>,
+}
+
+// Custom field with each part encrypted separately
+#[derive(Deserialize)]
+struct LegacyCustomField {
+    name: Vec<u8>,
+    field_type: Vec<u8>,
+    value: Vec<u8>,
+}
+
+// Kind-specific fields, which were encrypted apart from the rest of the entry
+#[derive(Deserialize)]
+struct LegacyPayload {
+    fields: BTreeMap<String, String>,
+}
+
+impl LegacyPasswordInfo {
+    // Decrypts every field into a single record
+    pub fn into_record(self) -> Record {
+        let payload = Zeroizing::new(decrypt_optional_field(&self.payload));
+
+        Record {
+            version: RECORD_VERSION,
+            kind: EntryKind::parse(&decrypt_optional_field(&self.kind)),
+            title: decrypt_field(&self.title),
+            user_id: decrypt_field(&self.user_id),
+            password: decrypt_field(&self.password),
+            url: decrypt_field(&self.url),
+            notes: decrypt_optional_field(&self.notes),
+            max_age_days: decrypt_optional_field(&self.max_age_days).parse().ok(),
+            custom_fields: self
+                .custom_fields
+                .iter()
+                .map(|field| CustomField {
+                    name: decrypt_field(&field.name),
+                    field_type: FieldType::parse(&decrypt_field(&field.field_type)),
+                    value: decrypt_field(&field.value),
+                })
+                .collect(),
+            fields: serde_json::from_str::<LegacyPayload>(&payload)
+                .map(|payload| payload.fields)
+                .unwrap_or_default(),
+            created: decrypt_timestamp(&self.created),
+            modified: decrypt_timestamp(&self.modified),
+            last_used: decrypt_timestamp(&self.last_used),
+            password_changed: decrypt_timestamp(&self.password_changed),
+            totp: None,
+            hotp: None,
+        }
+    }
+}
+
+// Decrypts a single encrypted field
+pub fn decrypt_field(ciphertext: &[u8]) -> String {
+    let plaintext = KEY
+        .with(|key| crypto::decrypt(ciphertext.to_vec(), key))
+        .expect("Vault is locked");
+    String::from_utf8(plaintext).unwrap_or_else(|_| "Invalid UTF-8".to_string())
+}
+
+// Decrypts a field that older entries may not have, giving an empty string for them
+fn decrypt_optional_field(ciphertext: &[u8]) -> String {
+    if ciphertext.is_empty() {
+        return String::new();
+    }
+    decrypt_field(ciphertext)
+}
+
+// Decrypts an encrypted timestamp, giving 0 for missing or unreadable ones
+fn decrypt_timestamp(ciphertext: &[u8]) -> u64 {
+    decrypt_optional_field(ciphertext).parse().unwrap_or(0)
+}
@@ -13,15 +13,18 @@
 
 use copypasta::{ClipboardContext, ClipboardProvider};
 use crossterm::{
-    event::{self, Event, KeyCode},
+    event::{self, Event, KeyCode, KeyModifiers},
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    widgets::{Block, Borders, Paragraph},
-    Terminal,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::block::{Position, Title},
+    widgets::{Block, Borders, List, ListItem as ListRow, ListState, Paragraph},
+    Frame, Terminal,
 };
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
@@ -29,60 +32,121 @@ use tokio::net::TcpStream;
 
 use std::error::Error;
 use std::io::stdout;
-use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
 use serde::{Deserialize, Serialize};
 use serde_json;
+use std::collections::BTreeMap;
 use std::str;
+use std::sync::Arc;
+use zeroize::{Zeroize, Zeroizing};
 
+mod agent;
+mod cli;
+mod config;
+mod credential;
 mod crypto;
+mod entry;
+mod legacy;
+mod memory;
+mod reference;
+mod search;
+mod session;
+mod ssh_agent;
+mod totp;
 
-// Global OnceLock for the key
-static KEY: OnceLock<[u8; 32]> = OnceLock::new();
+use entry::{CustomField, EntryKind, FieldType, Record};
 
-// Password Structure
+// Address of the password server
+const SERVER_ADDR: &str = "127.0.0.1:8080";
+
+// Key for the session, wiped when the vault locks
+static KEY: session::SessionKey = session::SessionKey::new();
+
+// Shown in place of hidden values, the same width whatever their length
+const MASK: &str = "********";
+
+// How long a status message is shown, and when it starts fading out
+const STATUS_DURATION: Duration = Duration::from_secs(5);
+const STATUS_FADE_AFTER: Duration = Duration::from_secs(3);
+
+// Password Structure, holding the whole entry as one encrypted record and the
+// data shown in the password list as another
 #[derive(Serialize, Deserialize)]
 struct PasswordInfo {
     title_hash: [u8; 32],
-    title: Vec<u8>,
-    user_id: Vec<u8>,
-    password: Vec<u8>,
-    url: Vec<u8>,
+    #[serde(default)]
+    record: Vec<u8>,
+    #[serde(default)]
+    summary: Vec<u8>,
 }
 
 // Server list item structure
 #[derive(Serialize, Deserialize)]
 struct ServerListItem {
     title_hash: [u8; 32],
+    #[serde(default)]
+    summary: Vec<u8>,
+    // Encrypted title and URL of entries stored before summaries existed
+    #[serde(default)]
     title: Vec<u8>,
+    #[serde(default)]
     url: Vec<u8>,
 }
 
-// Takes the info for a new password converts it to ciphertext and serializes it to JSON
-fn wrap_password(
-    title: String,
-    user_id: String,
-    password: String,
-    url: String,
-) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let title_hash = crypto::hash(crypto::HashInputType::Text(title.clone()));
+// Server trash item structure
+#[derive(Serialize, Deserialize)]
+struct ServerTrashItem {
+    title_hash: [u8; 32],
+    #[serde(default)]
+    summary: Vec<u8>,
+    #[serde(default)]
+    title: Vec<u8>,
+    #[serde(default)]
+    url: Vec<u8>,
+    deleted_at: u64,
+    expires_at: u64,
+}
+
+// Server revision structure, holding a previous version as stored JSON
+#[derive(Serialize, Deserialize)]
+struct ServerRevision {
+    replaced_at: u64,
+    record: String,
+}
 
-    // Get the key
-    let key = KEY.get().expect("Key not initialized");
+// Takes a record, encrypts it and its list summary, and serializes them to JSON
+fn wrap_password(record: &Record) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let title_hash = crypto::hash(crypto::HashInputType::Text(record.title.clone()));
+
+    let summary = ListItem {
+        title: record.title.clone(),
+        url: record.url.clone(),
+        user_id: record.user_id.clone(),
+        modified: record.modified,
+        last_used: record.last_used,
+        password_changed: record.password_changed,
+        max_age_days: record.max_age_days,
+        kind: record.kind,
+    };
 
-    // Encrypt the sensitive fields
-    let encrypted_title = crypto::encrypt(title, *key);
-    let encrypted_user_id = crypto::encrypt(user_id, *key);
-    let encrypted_password = crypto::encrypt(password, *key);
-    let encrypted_url = crypto::encrypt(url, *key);
+    // Encrypt both with the session key; the JSON is wiped once encrypted
+    let record_json = serde_json::to_string(record)?;
+    let summary_json = serde_json::to_string(&summary)?;
+    let (record, summary) = KEY
+        .with(|key| {
+            (
+                crypto::encrypt_padded(record_json, key),
+                crypto::encrypt_padded(summary_json, key),
+            )
+        })
+        .expect("Vault is locked");
 
     // Create PasswordInfo struct
     let password_info = PasswordInfo {
         title_hash,
-        title: encrypted_title,
-        user_id: encrypted_user_id,
-        password: encrypted_password,
-        url: encrypted_url,
+        record,
+        summary,
     };
 
     // Serialize to JSON
@@ -90,28 +154,205 @@ fn wrap_password(
     Ok(json_data)
 }
 
-// Send data to the server
+// Decrypts an entry as stored on the server, including ones stored field by field
+fn open_record(data: &[u8]) -> Result<Record, Box<dyn Error>> {
+    let password_info: PasswordInfo = serde_json::from_slice(data)?;
+    if password_info.record.is_empty() {
+        let legacy: legacy::LegacyPasswordInfo = serde_json::from_slice(data)?;
+        return Ok(legacy.into_record());
+    }
+
+    let plaintext = KEY
+        .with(|key| crypto::decrypt_padded(password_info.record, key))
+        .expect("Vault is locked");
+    let plaintext = Zeroizing::new(plaintext);
+    let record: Record = serde_json::from_slice(&plaintext)?;
+
+    // Fields from a newer format would be lost if this client saved the entry back
+    if record.version > entry::RECORD_VERSION {
+        return Err(format!(
+            "Entry uses record version {}, newer than this client supports",
+            record.version
+        )
+        .into());
+    }
+    Ok(record)
+}
+
+// Decrypts the list summary of an entry, falling back to the separately encrypted
+// title and URL of entries stored before summaries existed
+fn open_summary(summary: &[u8], title: &[u8], url: &[u8]) -> Option<ListItem> {
+    KEY.with(|key| {
+        if summary.is_empty() {
+            return match (
+                String::from_utf8(crypto::decrypt(title.to_vec(), key)),
+                String::from_utf8(crypto::decrypt(url.to_vec(), key)),
+            ) {
+                (Ok(title), Ok(url)) => Some(ListItem {
+                    title,
+                    url,
+                    ..ListItem::default()
+                }),
+                _ => None,
+            };
+        }
+        serde_json::from_slice(&crypto::decrypt_padded(summary.to_vec(), key)).ok()
+    })
+    .expect("Vault is locked")
+}
+
+// Whole days since a password was changed, or None if that was never recorded
+fn password_age_days(password_changed: u64) -> Option<u64> {
+    if password_changed == 0 {
+        return None;
+    }
+    Some(now_secs().saturating_sub(password_changed) / (24 * 60 * 60))
+}
+
+// Current time in seconds since the Unix epoch
+fn now_secs() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
+// Send data to the server, prefixed with the request type and data length
 async fn send(stream: &mut TcpStream, request_type: u8, data: &[u8]) -> Result<(), Box<dyn Error>> {
     let mut request = vec![request_type];
+    request.extend_from_slice(&(data.len() as u32).to_be_bytes());
     request.extend_from_slice(data);
 
     stream.write_all(&request).await?;
     Ok(())
 }
 
+// Sends a request and reads its response, turning server error responses into errors
+async fn request(
+    stream: &mut TcpStream,
+    request_type: u8,
+    data: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    send(stream, request_type, data).await?;
+    let (response_type, data) = receive(stream).await?;
+
+    if response_type == 0 {
+        return Err(String::from_utf8_lossy(&data).into_owned().into());
+    }
+    if response_type != request_type {
+        return Err(format!("Unexpected response type {}", response_type).into());
+    }
+    Ok(data)
+}
+
+// Encrypts and stores a password, keeping the creation time of any entry it replaces
+// and the password change time if the password itself is unchanged
+async fn store_password(stream: &mut TcpStream, mut record: Record) -> Result<(), Box<dyn Error>> {
+    let now = now_secs();
+    let title_hash = crypto::hash(crypto::HashInputType::Text(record.title.clone()));
+
+    // Only a missing entry counts as new. A failed lookup, or an entry this version can't
+    // open, stops the store rather than overwriting what is there
+    let data = request(stream, 2, &title_hash).await?;
+    let (created, password_changed) = if data.is_empty() {
+        (now, now)
+    } else {
+        let existing = open_record(&data)
+            .map_err(|e| format!("Can't open the existing \"{}\": {}", record.title, e))?;
+        let password_changed = if existing.password == record.password {
+            existing.password_changed
+        } else {
+            now
+        };
+        (existing.created, password_changed)
+    };
+
+    record.created = created;
+    record.password_changed = password_changed;
+    store_record(stream, record).await
+}
+
+// Encrypts and stores an entry, keeping the creation and password change times it has
+async fn store_record(stream: &mut TcpStream, mut record: Record) -> Result<(), Box<dyn Error>> {
+    let now = now_secs();
+    record.modified = now;
+    record.last_used = now;
+
+    let json = wrap_password(&record)?;
+    request(stream, 1, &json).await?;
+    Ok(())
+}
+
+// Records that a password was just used, without saving a new version of it
+async fn touch_password(stream: &mut TcpStream, record: &mut Record) -> Result<(), Box<dyn Error>> {
+    record.last_used = now_secs();
+
+    let json = wrap_password(record)?;
+    request(stream, 11, &json).await?;
+    Ok(())
+}
+
+// Generates the next HOTP code and saves the advanced counter before handing it out,
+// so the same code is never used twice
+async fn next_hotp_code(
+    stream: &mut TcpStream,
+    record: &mut Record,
+) -> Result<String, Box<dyn Error>> {
+    let hotp = record
+        .hotp
+        .as_mut()
+        .ok_or("No HOTP secret for this entry")?;
+    let code = hotp.code()?;
+    hotp.counter += 1;
+
+    let json = wrap_password(record)?;
+    request(stream, 11, &json).await?;
+    Ok(code)
+}
+
+// Re-encrypts entries stored field by field as single records, returning how many
+// were converted. Previous versions are left as they are and read as legacy records.
+async fn migrate_legacy_entries(stream: &mut TcpStream) -> Result<usize, Box<dyn Error>> {
+    let data = request(stream, 3, b"").await?;
+    let list = serde_json::from_slice::<Vec<ServerListItem>>(&data)?;
+
+    let mut migrated = 0;
+    for item in list.iter().filter(|item| item.summary.is_empty()) {
+        let data = request(stream, 2, &item.title_hash).await?;
+        let record = open_record(&data)?;
+        request(stream, 11, &wrap_password(&record)?).await?;
+        migrated += 1;
+    }
+    Ok(migrated)
+}
+
 // Input mode enum
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum InputMode {
     Command,
     Title,
+    EntryKindPrompt,
     UserId,
     Password,
     GeneratePasswordPrompt,
     PasswordLengthPrompt,
     Url,
+    MaxAge,
+    Totp,
+    Hotp,
+    SchemaField,
+    Notes,
+    CustomFieldPrompt,
+    CustomFieldName,
+    CustomFieldType,
+    CustomFieldValue,
     Delete,
+    ConfirmDelete,
+    Restore,
+    Purge,
+    ConfirmPurge,
+    RestoreVersion,
     Help,
     Get,
+    Search,
+    Unlock,
 }
 
 struct AppState {
@@ -121,66 +362,886 @@ struct AppState {
     user_id: String,
     password: String,
     url: String,
+    notes: String,
+    entry_kind: EntryKind,
+    max_age_days: Option<u64>,
+    totp: Option<totp::Totp>,
+    hotp: Option<totp::Hotp>,
+    // Last HOTP code generated for the open entry
+    hotp_code: Option<String>,
+    custom_fields: Vec<CustomField>,
+    custom_field_name: String,
+    custom_field_type: FieldType,
+    fields: BTreeMap<String, String>,
+    schema_field: usize,
+    config: config::Config,
     password_list: Vec<ListItem>,
-    current_password: Option<PasswordInfo>,
+    current_password: Option<Record>,
+    pending_delete: Option<Record>,
+    trash_list: Vec<TrashListItem>,
+    show_trash: bool,
+    // Title of the trashed password waiting for purge confirmation
+    pending_purge: Option<String>,
+    history: Vec<HistoryItem>,
+    show_history: bool,
+    show_stale: bool,
     show_password: bool,
     waiting_for_second_key: Option<char>,
-    current_page: usize,
+    // Highlighted rows of the password list and the search results
+    list_state: ListState,
+    search_state: ListState,
+    // Rows moved by 'n' and 'p'
     items_per_page: usize,
+    // Entry whose values fill in the prompts while it is being edited
+    editing: Option<Record>,
+    // Copied value waiting to be cleared from the clipboard
+    clipboard: Option<ClipboardGuard>,
+    // Time of the last key press, for locking the vault when left idle
+    last_activity: Instant,
+    // Keys of SSH key entries, while the SSH agent is running
+    ssh_keyring: Option<Arc<ssh_agent::Keyring>>,
+    // Use of an SSH key waiting for the user to allow or refuse it
+    ssh_confirmation: Option<ssh_agent::Confirmation>,
+    status: Option<StatusMessage>,
+    sort_mode: SortMode,
+}
+
+// Orderings available for the password list
+#[derive(Clone, Copy, PartialEq)]
+enum SortMode {
+    Title,
+    RecentlyUsed,
+    RecentlyModified,
+}
+
+impl SortMode {
+    fn next(self) -> SortMode {
+        match self {
+            SortMode::Title => SortMode::RecentlyUsed,
+            SortMode::RecentlyUsed => SortMode::RecentlyModified,
+            SortMode::RecentlyModified => SortMode::Title,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SortMode::Title => "title",
+            SortMode::RecentlyUsed => "recently used",
+            SortMode::RecentlyModified => "recently modified",
+        }
+    }
+}
+
+// A value we put on the clipboard, cleared once the configured delay has passed
+struct ClipboardGuard {
+    value: String,
+    copied_at: Instant,
+}
+
+impl Drop for ClipboardGuard {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+// Result of the last operation, shown in the status bar
+struct StatusMessage {
+    text: String,
+    is_error: bool,
+    timestamp: String,
+    shown_at: Instant,
+}
+
+// Wipes anything still being typed when the client exits
+impl Drop for AppState {
+    fn drop(&mut self) {
+        reset_entry_prompts(self);
+    }
+}
+
+impl AppState {
+    fn set_status(&mut self, text: impl Into<String>, is_error: bool) {
+        self.status = Some(StatusMessage {
+            text: text.into(),
+            is_error,
+            timestamp: chrono::Local::now().format("%H:%M:%S").to_string(),
+            shown_at: Instant::now(),
+        });
+    }
 }
 
-// Struct to store password list items
-#[derive(Clone)]
+// Struct to store password list items, also encrypted as each entry's list summary
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 struct ListItem {
     title: String,
     url: String,
+    user_id: String,
+    modified: u64,
+    last_used: u64,
+    password_changed: u64,
+    max_age_days: Option<u64>,
+    kind: EntryKind,
+}
+
+// Struct to store trashed password list items
+struct TrashListItem {
+    title: String,
+    url: String,
+    deleted_at: u64,
+    expires_at: u64,
+}
+
+// Struct to store a previous version of the current password
+struct HistoryItem {
+    index: u32,
+    replaced_at: u64,
+    record: Record,
+}
+
+// Formats a Unix timestamp in local time for display
+fn format_timestamp(secs: u64) -> String {
+    if secs == 0 {
+        return "Unknown".to_string();
+    }
+
+    chrono::DateTime::from_timestamp(secs as i64, 0)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+// Entries matching a search query by title and URL, and username if enabled, best match first
+fn rank_entries(items: &[ListItem], query: &str, search_usernames: bool) -> Vec<search::Ranked> {
+    let entries: Vec<Vec<&str>> = items
+        .iter()
+        .map(|item| {
+            let mut fields = vec![item.title.as_str(), item.url.as_str()];
+            if search_usernames {
+                fields.push(item.user_id.as_str());
+            }
+            fields
+        })
+        .collect();
+
+    search::rank(query, &entries)
+}
+
+// Entries matching the search query, best match first
+fn search_results(app_state: &AppState) -> Vec<search::Ranked> {
+    rank_entries(
+        &app_state.password_list,
+        &app_state.input,
+        app_state.config.search_usernames,
+    )
+}
+
+// Splits text into spans with the matched characters highlighted
+fn highlight(text: &str, positions: &[usize]) -> Vec<Span<'static>> {
+    let matched = Style::default()
+        .fg(Color::Yellow)
+        .add_modifier(Modifier::BOLD);
+
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if positions.contains(&i) {
+                Span::styled(c.to_string(), matched)
+            } else {
+                Span::raw(c.to_string())
+            }
+        })
+        .collect()
+}
+
+// Whether the password list is showing, rather than search or another view over it
+fn list_view_active(app_state: &AppState) -> bool {
+    !matches!(
+        app_state.input_mode,
+        InputMode::Help | InputMode::Search | InputMode::Unlock
+    ) && app_state.pending_delete.is_none()
+        && app_state.current_password.is_none()
+        && !app_state.show_history
+        && !app_state.show_stale
+        && !app_state.show_trash
+}
+
+// The highlighted entry, taken from the search results while searching
+fn selected_item(app_state: &AppState) -> Option<&ListItem> {
+    if app_state.input_mode == InputMode::Search {
+        let results = search_results(app_state);
+        let result = results.get(app_state.search_state.selected()?)?;
+        return app_state.password_list.get(result.index);
+    }
+    app_state
+        .password_list
+        .get(app_state.list_state.selected()?)
+}
+
+// Title of the entry that 'd', 'e', and 'c' act on: the open one, or else the highlighted one
+fn target_title(app_state: &AppState) -> Option<String> {
+    match &app_state.current_password {
+        Some(record) => Some(record.title.clone()),
+        None if list_view_active(app_state) => {
+            selected_item(app_state).map(|item| item.title.clone())
+        }
+        None => None,
+    }
+}
+
+// Moves the highlight by the given number of rows, stopping at either end
+fn move_selection(app_state: &mut AppState, offset: isize) {
+    let (len, state) = if app_state.input_mode == InputMode::Search {
+        (search_results(app_state).len(), &mut app_state.search_state)
+    } else {
+        (app_state.password_list.len(), &mut app_state.list_state)
+    };
+
+    if len == 0 {
+        state.select(None);
+        return;
+    }
+    let current = state.selected().unwrap_or(0) as isize;
+    state.select(Some((current + offset).clamp(0, len as isize - 1) as usize));
+}
+
+// Keeps the highlight on the given entry, or in the same place if it is gone
+fn restore_selection(app_state: &mut AppState, title: Option<String>) {
+    let len = app_state.password_list.len();
+    let position = title.and_then(|title| {
+        app_state
+            .password_list
+            .iter()
+            .position(|item| item.title == title)
+    });
+
+    let selected = match position {
+        Some(position) => Some(position),
+        None if len == 0 => None,
+        None => Some(app_state.list_state.selected().unwrap_or(0).min(len - 1)),
+    };
+    app_state.list_state.select(selected);
+}
+
+// Background of the highlighted row
+fn selected_style() -> Style {
+    Style::default()
+        .bg(Color::DarkGray)
+        .add_modifier(Modifier::BOLD)
+}
+
+// Password list with the highlighted entry marked
+fn render_password_list(frame: &mut Frame, app_state: &mut AppState, area: Rect) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(
+            "Password Manager - {} items, sorted by {}",
+            app_state.password_list.len(),
+            app_state.sort_mode.label()
+        ))
+        .title(
+            Title::from("j/k: move, Enter: open, e: edit, d: delete, c-p/c-u: copy")
+                .position(Position::Bottom),
+        );
+
+    if app_state.password_list.is_empty() {
+        frame.render_widget(Paragraph::new("No passwords stored.").block(block), area);
+        return;
+    }
+
+    let rows: Vec<ListRow> = app_state
+        .password_list
+        .iter()
+        .map(|item| {
+            ListRow::new(vec![
+                Line::from(format!("Title: {}", item.title)),
+                Line::from(format!("URL: {}", item.url)),
+                Line::from(""),
+            ])
+        })
+        .collect();
+    let list = List::new(rows)
+        .block(block)
+        .highlight_style(selected_style())
+        .highlight_symbol("> ");
+    frame.render_stateful_widget(list, area, &mut app_state.list_state);
+}
+
+// Search results with the matching characters of each field highlighted
+fn render_search(frame: &mut Frame, app_state: &mut AppState, area: Rect) {
+    let results = search_results(app_state);
+    let block = Block::default().borders(Borders::ALL).title(format!(
+        "Search Results ({} of {})",
+        results.len(),
+        app_state.password_list.len()
+    ));
+
+    if results.is_empty() {
+        frame.render_widget(Paragraph::new("No matching passwords.").block(block), area);
+        return;
+    }
+
+    let labels = ["Title: ", "URL: ", "Username: "];
+    let rows: Vec<ListRow> = results
+        .iter()
+        .map(|result| {
+            let item = &app_state.password_list[result.index];
+            let fields = [&item.title, &item.url, &item.user_id];
+
+            let mut lines = Vec::new();
+            for (position, (label, value)) in labels.iter().zip(fields).enumerate() {
+                // Usernames are only shown when they matched
+                let positions: &[usize] = result.positions.get(position).map_or(&[], Vec::as_slice);
+                if position == 2 && positions.is_empty() {
+                    continue;
+                }
+
+                let mut spans = vec![Span::raw(*label)];
+                spans.extend(highlight(value, positions));
+                lines.push(Line::from(spans));
+            }
+            lines.push(Line::from(""));
+            ListRow::new(lines)
+        })
+        .collect();
+    let list = List::new(rows)
+        .block(block)
+        .highlight_style(selected_style())
+        .highlight_symbol("> ");
+    frame.render_stateful_widget(list, area, &mut app_state.search_state);
+}
+
+// Fetches and decrypts an entry by title, or gives None if there is no such entry
+async fn find_record(
+    stream: &mut TcpStream,
+    title: &str,
+) -> Result<Option<Record>, Box<dyn Error>> {
+    let title_hash = crypto::hash(crypto::HashInputType::Text(title.to_string()));
+
+    // Send get request (type 2) with the title hash; the server sends no data if it
+    // has nothing stored under it
+    let data = request(stream, 2, &title_hash).await?;
+    if data.is_empty() {
+        return Ok(None);
+    }
+    open_record(&data).map(Some)
+}
+
+// Fetches and decrypts an entry by title, treating a missing entry as an error
+async fn fetch_record(stream: &mut TcpStream, title: &str) -> Result<Record, Box<dyn Error>> {
+    find_record(stream, title)
+        .await?
+        .ok_or_else(|| "Password not found".into())
+}
+
+// Opens an entry's details by title and records that it was used
+async fn open_entry(stream: &mut TcpStream, app_state: &mut AppState, title: &str) {
+    match fetch_record(stream, title).await {
+        Ok(mut record) => {
+            match touch_password(stream, &mut record).await {
+                Ok(_) => {
+                    if let Some(item) = app_state
+                        .password_list
+                        .iter_mut()
+                        .find(|item| item.title == title)
+                    {
+                        item.last_used = now_secs();
+                    }
+                    sort_password_list(app_state);
+                }
+                Err(e) => app_state.set_status(format!("Failed to record use: {}", e), true),
+            }
+            app_state.current_password = Some(record);
+            app_state.hotp_code = None;
+        }
+        Err(e) => app_state.set_status(format!("Get failed: {}", e), true),
+    }
+}
+
+// Looks an entry up by title and shows it for confirmation before deleting it
+async fn confirm_delete(stream: &mut TcpStream, app_state: &mut AppState, title: &str) {
+    match find_record(stream, title).await {
+        Ok(Some(record)) => {
+            app_state.current_password = None;
+            app_state.hotp_code = None;
+            app_state.pending_delete = Some(record);
+            app_state.input_mode = InputMode::ConfirmDelete;
+        }
+        Ok(None) => app_state.set_status(format!("No password titled \"{}\"", title), true),
+        Err(e) => app_state.set_status(format!("Get failed: {}", e), true),
+    }
+}
+
+// Shows a trashed entry for confirmation before purging it
+fn confirm_purge(app_state: &mut AppState, title: String) {
+    if app_state.trash_list.iter().any(|item| item.title == title) {
+        app_state.pending_purge = Some(title);
+        app_state.input_mode = InputMode::ConfirmPurge;
+    } else {
+        app_state.set_status(
+            format!("No password titled \"{}\" in the trash", title),
+            true,
+        );
+    }
+}
+
+// Restores or purges a trashed entry, then refreshes the trash and password lists
+async fn trash_request(
+    stream: &mut TcpStream,
+    app_state: &mut AppState,
+    title: &str,
+    request_type: u8,
+    action: &str,
+) {
+    let title_hash = crypto::hash(crypto::HashInputType::Text(title.to_string()));
+    if let Err(e) = request(stream, request_type, &title_hash).await {
+        app_state.set_status(format!("{} failed: {}", action, e), true);
+        return;
+    }
+
+    app_state.set_status(format!("{}d \"{}\"", action, title), false);
+    let refreshed = match update_trash_list(stream, app_state).await {
+        Ok(_) => update_password_list(stream, app_state).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = refreshed {
+        app_state.set_status(format!("Failed to refresh lists: {}", e), true);
+    }
+}
+
+// Starts the entry prompts with an existing entry's values filled in
+async fn start_edit(stream: &mut TcpStream, app_state: &mut AppState, title: &str) {
+    match fetch_record(stream, title).await {
+        Ok(record) => {
+            app_state.current_password = None;
+            app_state.hotp_code = None;
+            app_state.show_password = false;
+            app_state.custom_fields = record.custom_fields.clone();
+            app_state.input = record.title.clone();
+            app_state.input_mode = InputMode::Title;
+            app_state.editing = Some(record);
+        }
+        Err(e) => app_state.set_status(format!("Get failed: {}", e), true),
+    }
+}
+
+// Value the entry being edited already has for the prompt being shown
+fn edit_prefill(app_state: &AppState) -> Option<String> {
+    let record = app_state.editing.as_ref()?;
+    let value = match app_state.input_mode {
+        InputMode::EntryKindPrompt => match record.kind {
+            EntryKind::Login => "l",
+            EntryKind::SecureNote => "n",
+            EntryKind::Card => "c",
+            EntryKind::Identity => "i",
+            EntryKind::SshKey => "s",
+            EntryKind::ApiToken => "a",
+        }
+        .to_string(),
+        InputMode::UserId => record.user_id.clone(),
+        InputMode::GeneratePasswordPrompt => "n".to_string(),
+        InputMode::Password => record.password.clone(),
+        InputMode::Url => record.url.clone(),
+        InputMode::MaxAge => record
+            .max_age_days
+            .map(|days| days.to_string())
+            .unwrap_or_default(),
+        InputMode::Totp => record
+            .totp
+            .as_ref()
+            .map(totp::Totp::uri)
+            .unwrap_or_default(),
+        InputMode::Hotp => record
+            .hotp
+            .as_ref()
+            .map(totp::Hotp::uri)
+            .unwrap_or_default(),
+        InputMode::SchemaField => current_schema_field(app_state)
+            .and_then(|field| record.fields.get(field.name))
+            .cloned()
+            .unwrap_or_default(),
+        InputMode::Notes => record.notes.clone(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+// The open entry to copy from, or else the highlighted one fetched from the server
+async fn copy_source(stream: &mut TcpStream, app_state: &mut AppState) -> Option<Record> {
+    if let Some(record) = &app_state.current_password {
+        return Some(record.clone());
+    }
+    let title = target_title(app_state)?;
+    match fetch_record(stream, &title).await {
+        Ok(record) => Some(record),
+        Err(e) => {
+            app_state.set_status(format!("Get failed: {}", e), true);
+            None
+        }
+    }
 }
 
 // Receive and parse server responses
 async fn receive(stream: &mut TcpStream) -> Result<(u8, Vec<u8>), Box<dyn Error>> {
-    let mut buffer = vec![0; 8192];
-    let n = stream.read(&mut buffer).await?;
-    if n == 0 {
-        return Err("Connection closed by server".into());
+    let mut header = [0; 5];
+    if let Err(e) = stream.read_exact(&mut header).await {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            return Err("Connection closed by server".into());
+        }
+        return Err(e.into());
     }
 
-    let response_type = buffer[0];
-    let data = buffer[1..n].to_vec();
+    let response_type = header[0];
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    let mut data = vec![0; length];
+    stream.read_exact(&mut data).await?;
     Ok((response_type, data))
 }
 
+// Fetches and decrypts the summaries of every stored entry
+async fn fetch_password_list(stream: &mut TcpStream) -> Result<Vec<ListItem>, Box<dyn Error>> {
+    let data = request(stream, 3, b"").await?;
+    let list = serde_json::from_slice::<Vec<ServerListItem>>(&data)?;
+
+    Ok(list
+        .iter()
+        .filter_map(|item| open_summary(&item.summary, &item.title, &item.url))
+        .collect())
+}
+
 // Update the password list from the server
 async fn update_password_list(
     stream: &mut TcpStream,
     app_state: &mut AppState,
 ) -> Result<(), Box<dyn Error>> {
-    if let Ok(_) = send(stream, 3, b"").await {
-        if let Ok((response_type, data)) = receive(stream).await {
-            if response_type == 3 {
-                if let Ok(list) = serde_json::from_slice::<Vec<ServerListItem>>(&data) {
-                    let key = KEY.get().expect("Key not initialized");
-
-                    app_state.password_list = list
-                        .into_iter()
-                        .filter_map(|item| {
-                            match (
-                                String::from_utf8(crypto::decrypt(item.title, *key)),
-                                String::from_utf8(crypto::decrypt(item.url, *key)),
-                            ) {
-                                (Ok(title), Ok(url)) => Some(ListItem { title, url }),
-                                _ => None,
-                            }
-                        })
-                        .collect();
-
-                    // Sort the password list by title
-                    app_state
-                        .password_list
-                        .sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()));
+    let selected = selected_title(app_state);
+    app_state.password_list = fetch_password_list(stream).await?;
+
+    sort_password_list(app_state);
+    restore_selection(app_state, selected);
+
+    Ok(())
+}
+
+// Title of the highlighted row of the password list
+fn selected_title(app_state: &AppState) -> Option<String> {
+    let position = app_state.list_state.selected()?;
+    app_state
+        .password_list
+        .get(position)
+        .map(|item| item.title.clone())
+}
+
+// Sort the password list by the selected ordering, falling back to title
+fn sort_password_list(app_state: &mut AppState) {
+    let selected = selected_title(app_state);
+    let by_title = |a: &ListItem, b: &ListItem| a.title.to_lowercase().cmp(&b.title.to_lowercase());
+
+    match app_state.sort_mode {
+        SortMode::Title => app_state.password_list.sort_by(by_title),
+        SortMode::RecentlyUsed => app_state
+            .password_list
+            .sort_by(|a, b| b.last_used.cmp(&a.last_used).then_with(|| by_title(a, b))),
+        SortMode::RecentlyModified => app_state
+            .password_list
+            .sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| by_title(a, b))),
+    }
+
+    restore_selection(app_state, selected);
+}
+
+// Update the trash list from the server
+async fn update_trash_list(
+    stream: &mut TcpStream,
+    app_state: &mut AppState,
+) -> Result<(), Box<dyn Error>> {
+    let data = request(stream, 6, b"").await?;
+    let list = serde_json::from_slice::<Vec<ServerTrashItem>>(&data)?;
+
+    app_state.trash_list = list
+        .iter()
+        .filter_map(|item| {
+            open_summary(&item.summary, &item.title, &item.url).map(|summary| TrashListItem {
+                title: summary.title,
+                url: summary.url,
+                deleted_at: item.deleted_at,
+                expires_at: item.expires_at,
+            })
+        })
+        .collect();
+
+    // Most recently deleted first
+    app_state
+        .trash_list
+        .sort_by_key(|item| std::cmp::Reverse(item.deleted_at));
+
+    Ok(())
+}
+
+// Stores the password that was just entered and resets the entry prompts
+async fn save_entered_password(stream: &mut TcpStream, app_state: &mut AppState) {
+    let record = Record {
+        kind: app_state.entry_kind,
+        title: std::mem::take(&mut app_state.title),
+        user_id: std::mem::take(&mut app_state.user_id),
+        password: std::mem::take(&mut app_state.password),
+        url: std::mem::take(&mut app_state.url),
+        notes: std::mem::take(&mut app_state.notes),
+        max_age_days: app_state.max_age_days.take(),
+        totp: app_state.totp.take(),
+        hotp: app_state.hotp.take(),
+        custom_fields: std::mem::take(&mut app_state.custom_fields),
+        fields: std::mem::take(&mut app_state.fields),
+        version: entry::RECORD_VERSION,
+        created: 0,
+        modified: 0,
+        last_used: 0,
+        password_changed: 0,
+    };
+    let title = record.title.clone();
+    let renamed = app_state
+        .editing
+        .take()
+        .filter(|previous| previous.title != title);
+
+    let result = match &renamed {
+        Some(previous) => store_renamed(stream, record, previous).await,
+        None => store_password(stream, record).await,
+    };
+    match result {
+        Ok(_) => {
+            app_state.set_status(format!("Stored \"{}\"", title), false);
+
+            // A renamed entry is stored under its new title, so the old one goes to the trash
+            if let Some(previous) = renamed {
+                if let Err(e) = request(stream, 5, &previous.title_hash()).await {
+                    app_state.set_status(
+                        format!("Failed to remove \"{}\": {}", previous.title, e),
+                        true,
+                    );
                 }
             }
+            if let Err(e) = update_password_list(stream, app_state).await {
+                app_state.set_status(format!("Failed to refresh password list: {}", e), true);
+            }
+        }
+        Err(e) => app_state.set_status(format!("Store failed: {}", e), true),
+    }
+
+    app_state.input.clear();
+    app_state.entry_kind = EntryKind::Login;
+    app_state.input_mode = InputMode::Command;
+}
+
+// Stores an edited entry under its new title, keeping the times of the entry it replaces
+async fn store_renamed(
+    stream: &mut TcpStream,
+    mut record: Record,
+    previous: &Record,
+) -> Result<(), Box<dyn Error>> {
+    if find_record(stream, &record.title).await?.is_some() {
+        return Err(format!("\"{}\" already exists", record.title).into());
+    }
+    record.created = previous.created;
+    record.password_changed = if record.password == previous.password {
+        previous.password_changed
+    } else {
+        now_secs()
+    };
+    store_record(stream, record).await
+}
+
+// The schema field being entered for a new entry, if any
+fn current_schema_field(app_state: &AppState) -> Option<&'static entry::SchemaField> {
+    if app_state.input_mode != InputMode::SchemaField {
+        return None;
+    }
+    app_state.entry_kind.schema().get(app_state.schema_field)
+}
+
+// Whether the input box takes several lines, so Enter adds a line and Ctrl+S finishes
+fn input_is_multiline(app_state: &AppState) -> bool {
+    app_state.input_mode == InputMode::Notes
+        || current_schema_field(app_state).is_some_and(|field| field.multiline)
+}
+
+// Records the value entered for the current schema field and moves on to the next one
+fn submit_schema_field(app_state: &mut AppState) {
+    let kind = app_state.entry_kind;
+    let Some(field) = current_schema_field(app_state) else {
+        return;
+    };
+
+    if let Err(e) = entry::validate(kind, field.name, &app_state.input) {
+        app_state.set_status(e, true);
+        return;
+    }
+
+    app_state
+        .fields
+        .insert(field.name.to_string(), std::mem::take(&mut app_state.input));
+    app_state.schema_field += 1;
+    if app_state.schema_field >= kind.schema().len() {
+        app_state.input_mode = InputMode::Notes;
+    }
+}
+
+// Clears the values entered for a new or edited entry
+fn reset_entry_prompts(app_state: &mut AppState) {
+    app_state.input.zeroize();
+    app_state.title.clear();
+    app_state.user_id.clear();
+    app_state.password.zeroize();
+    app_state.url.clear();
+    app_state.notes.zeroize();
+    app_state.max_age_days = None;
+    app_state.totp = None;
+    app_state.hotp = None;
+    app_state.entry_kind = EntryKind::Login;
+    app_state.custom_fields.clear();
+    app_state.custom_field_name.clear();
+    for value in app_state.fields.values_mut() {
+        value.zeroize();
+    }
+    app_state.fields.clear();
+    app_state.schema_field = 0;
+}
+
+// Wipes the session key and drops everything decrypted with it, leaving only the
+// prompt for the master password
+async fn lock_vault(app_state: &mut AppState) {
+    KEY.lock();
+    clear_clipboard(app_state);
+
+    // The agent's copy would let the next command in without the password; there may
+    // be no agent running
+    let _ = agent::lock().await;
+
+    app_state.password_list.clear();
+    app_state.list_state.select(None);
+    app_state.search_state.select(None);
+    app_state.current_password = None;
+    app_state.pending_delete = None;
+    app_state.editing = None;
+    app_state.trash_list.clear();
+    app_state.show_trash = false;
+    app_state.pending_purge = None;
+    app_state.history.clear();
+    app_state.show_history = false;
+    app_state.show_stale = false;
+    app_state.show_password = false;
+    app_state.hotp_code = None;
+    app_state.waiting_for_second_key = None;
+    reset_entry_prompts(app_state);
+
+    // Stop serving SSH keys, refusing any use still waiting for an answer
+    if let Some(keyring) = &app_state.ssh_keyring {
+        keyring.clear();
+    }
+    app_state.ssh_confirmation = None;
+
+    app_state.input_mode = InputMode::Unlock;
+    app_state.set_status("Locked after inactivity", false);
+}
+
+// Loads the keys of SSH key entries into the SSH agent, if it is running
+async fn load_ssh_keys(stream: &mut TcpStream, app_state: &mut AppState) {
+    let Some(keyring) = app_state.ssh_keyring.clone() else {
+        return;
+    };
+    match ssh_agent::load_identities(stream).await {
+        Ok((identities, failures)) => {
+            keyring.replace(identities);
+            if !failures.is_empty() {
+                app_state.set_status(
+                    format!("Failed to load SSH keys: {}", failures.join(", ")),
+                    true,
+                );
+            }
+        }
+        Err(e) => app_state.set_status(format!("Failed to load SSH keys: {}", e), true),
+    }
+}
+
+// Copies a value to the clipboard and reports the result in the status bar
+fn copy_to_clipboard(app_state: &mut AppState, value: String, label: &str) {
+    let copied = ClipboardContext::new().and_then(|mut ctx| ctx.set_contents(value.clone()));
+    match copied {
+        Ok(_) => {
+            app_state.set_status(format!("{} copied to clipboard", label), false);
+            if app_state.config.clipboard_clear_secs > 0 {
+                app_state.clipboard = Some(ClipboardGuard {
+                    value,
+                    copied_at: Instant::now(),
+                });
+            }
+        }
+        Err(e) => app_state.set_status(format!("Copy failed: {}", e), true),
+    }
+}
+
+// Seconds left before the copied value is cleared from the clipboard
+fn clipboard_remaining(app_state: &AppState) -> Option<u64> {
+    let guard = app_state.clipboard.as_ref()?;
+    Some(
+        app_state
+            .config
+            .clipboard_clear_secs
+            .saturating_sub(guard.copied_at.elapsed().as_secs()),
+    )
+}
+
+// Clears the clipboard if it still holds the value we copied, leaving anything
+// copied since then alone
+fn clear_clipboard(app_state: &mut AppState) {
+    let Some(guard) = app_state.clipboard.take() else {
+        return;
+    };
+
+    let cleared = ClipboardContext::new().and_then(|mut ctx| {
+        if ctx
+            .get_contents()
+            .is_ok_and(|contents| contents == guard.value)
+        {
+            ctx.set_contents(String::new())?;
+            return Ok(true);
         }
+        Ok(false)
+    });
+    match cleared {
+        Ok(true) => app_state.set_status("Clipboard cleared", false),
+        Ok(false) => {}
+        Err(e) => app_state.set_status(format!("Failed to clear clipboard: {}", e), true),
     }
+}
+
+// Load the previous versions of a password from the server, newest first
+async fn update_history(
+    stream: &mut TcpStream,
+    app_state: &mut AppState,
+    title_hash: &[u8; 32],
+) -> Result<(), Box<dyn Error>> {
+    let data = request(stream, 9, title_hash).await?;
+    let revisions = serde_json::from_slice::<Vec<ServerRevision>>(&data)?;
+
+    app_state.history = revisions
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, revision)| {
+            open_record(revision.record.as_bytes())
+                .ok()
+                .map(|record| HistoryItem {
+                    index: index as u32,
+                    replaced_at: revision.replaced_at,
+                    record,
+                })
+        })
+        .rev()
+        .collect();
+
     Ok(())
 }
 
@@ -188,67 +1249,93 @@ async fn update_password_list(
 async fn validate_password(
     password: &str,
     stream: &mut TcpStream,
-) -> Result<[u8; 32], Box<dyn Error>> {
+) -> Result<Option<Zeroizing<[u8; 32]>>, Box<dyn Error>> {
     // Derive key from the password
-    let derived_key = crypto::key_derivation(password.to_string());
+    validate_key(crypto::key_derivation(password), stream, true).await
+}
 
+// Checks a key against the stored entries, handing it back if it decrypts them and
+// giving None if it doesn't. An error means the check itself failed. An empty vault
+// takes any key as its new master key only if new_vault is set, since an agent's key
+// may belong to another vault.
+async fn validate_key(
+    derived_key: Zeroizing<[u8; 32]>,
+    stream: &mut TcpStream,
+    new_vault: bool,
+) -> Result<Option<Zeroizing<[u8; 32]>>, Box<dyn Error>> {
     // Request the password list from the server to test decryption
-    if let Ok(_) = send(stream, 3, b"").await {
-        if let Ok((response_type, data)) = receive(stream).await {
-            if response_type == 3 {
-                if let Ok(list) = serde_json::from_slice::<Vec<ServerListItem>>(&data) {
-                    // Try to decrypt the first item's title
-                    if !list.is_empty() {
-                        // Try to decrypt and catch any errors
-                        match std::panic::catch_unwind(|| {
-                            crypto::decrypt(list[0].title.clone(), derived_key)
-                        }) {
-                            Ok(title_bytes) => {
-                                // Successfully decrypted, check if it's valid UTF-8
-                                if String::from_utf8(title_bytes).is_ok() {
-                                    return Ok(derived_key);
-                                }
-                            }
-                            Err(_) => {
-                                // Decryption failed - wrong password
-                                return Err("Invalid password".into());
-                            }
-                        }
-                    } else {
-                        // No passwords yet - sets new master password
-                        return Ok(derived_key);
-                    }
-                }
-            }
-        }
-    }
+    let data = request(stream, 3, b"").await?;
+    let list: Vec<ServerListItem> = serde_json::from_slice(&data)?;
+
+    // No passwords yet - sets new master password
+    let Some(item) = list.first() else {
+        return Ok(new_vault.then_some(derived_key));
+    };
 
-    Err("Invalid password".into())
+    // Try to decrypt the first item's summary, or title for older entries; a wrong key
+    // fails to decrypt
+    let decrypted = if item.summary.is_empty() {
+        crypto::try_decrypt(item.title.clone(), &derived_key)
+    } else {
+        crypto::try_decrypt_padded(item.summary.clone(), &derived_key)
+    };
+    match decrypted {
+        Ok(title_bytes) if std::str::from_utf8(&title_bytes).is_ok() => Ok(Some(derived_key)),
+        _ => Ok(None),
+    }
 }
 
 // Main client function
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    // Keep the key and decrypted entries out of core dumps
+    if let Err(e) = memory::disable_core_dumps() {
+        eprintln!("Warning: failed to disable core dumps: {}", e);
+    }
+
+    // Run a single command and exit when given arguments, instead of starting the TUI
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(args).await);
+    }
+
     // connect to server
-    let mut stream = TcpStream::connect("127.0.0.1:8080").await?;
+    let mut stream = TcpStream::connect(SERVER_ADDR).await?;
+
+    // Use the key held by a running agent, if it still opens the vault
+    let agent_key = match agent::fetch_key().await {
+        Some(key) => validate_key(key, &mut stream, false).await?,
+        None => None,
+    };
 
     // Password validation loop
-    let derived_key = loop {
-        let input =
-            rpassword::prompt_password("Enter Password: ").expect("Failed to read password");
-
-        match validate_password(&input, &mut stream).await {
-            Ok(key) => break key,
-            Err(_) => {
-                println!("Invalid password. Please try again.");
-                continue;
+    let derived_key = match agent_key {
+        Some(key) => key,
+        None => loop {
+            let input = Zeroizing::new(
+                rpassword::prompt_password("Enter Password: ").expect("Failed to read password"),
+            );
+
+            match validate_password(&input, &mut stream).await? {
+                Some(key) => {
+                    // Spare later runs the prompt; there may be no agent running
+                    let _ = agent::add_key(&key).await;
+                    break key;
+                }
+                None => {
+                    println!("Invalid password. Please try again.");
+                    continue;
+                }
             }
-        }
+        },
     };
 
-    // Now store the validated key
-    KEY.set(derived_key)
-        .expect("Key has already been initialized");
+    // Now store the validated key; this copy is wiped when it goes out of scope
+    if let Err(e) = KEY.unlock(&derived_key) {
+        eprintln!("Warning: failed to lock the session key in memory: {}", e);
+    }
+
+    let config = config::load()?;
 
     // Setup terminal
     enable_raw_mode()?;
@@ -257,9 +1344,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // Send request for password list
-    send(&mut stream, 3, b"").await?;
-
     // display-able state options
     let mut app_state = AppState {
         input: String::new(),
@@ -268,138 +1352,450 @@ async fn main() -> Result<(), Box<dyn Error>> {
         user_id: String::new(),
         password: String::new(),
         url: String::new(),
+        notes: String::new(),
+        entry_kind: EntryKind::Login,
+        max_age_days: None,
+        totp: None,
+        hotp: None,
+        hotp_code: None,
+        custom_fields: Vec::new(),
+        custom_field_name: String::new(),
+        custom_field_type: FieldType::Text,
+        fields: BTreeMap::new(),
+        schema_field: 0,
+        config,
         password_list: Vec::new(),
         current_password: None,
+        pending_delete: None,
+        pending_purge: None,
+        trash_list: Vec::new(),
+        show_trash: false,
+        history: Vec::new(),
+        show_history: false,
+        show_stale: false,
         show_password: false,
         waiting_for_second_key: None,
-        current_page: 0,
+        list_state: ListState::default(),
+        search_state: ListState::default(),
         items_per_page: 10,
+        editing: None,
+        clipboard: None,
+        last_activity: Instant::now(),
+        ssh_keyring: None,
+        ssh_confirmation: None,
+        status: None,
+        sort_mode: SortMode::Title,
     };
 
-    // After sending the initial list request, receive and process the response
-    let (response_type, data) = receive(&mut stream).await?;
-    if response_type == 3 {
-        if let Ok(list) = serde_json::from_slice::<Vec<ServerListItem>>(&data) {
-            let key = KEY.get().expect("Key not initialized");
-
-            app_state.password_list = list
-                .into_iter()
-                .filter_map(|item| {
-                    match (
-                        String::from_utf8(crypto::decrypt(item.title, *key)),
-                        String::from_utf8(crypto::decrypt(item.url, *key)),
-                    ) {
-                        (Ok(title), Ok(url)) => Some(ListItem { title, url }),
-                        _ => None,
-                    }
-                })
-                .collect();
+    // Convert entries stored field by field before loading the list
+    match migrate_legacy_entries(&mut stream).await {
+        Ok(0) => {}
+        Ok(count) => app_state.set_status(format!("Migrated {} entries", count), false),
+        Err(e) => app_state.set_status(format!("Failed to migrate entries: {}", e), true),
+    }
+
+    // Load the initial password list
+    if let Err(e) = update_password_list(&mut stream, &mut app_state).await {
+        app_state.set_status(format!("Failed to load password list: {}", e), true);
+    }
 
-            // Sort the password list by title
-            app_state
-                .password_list
-                .sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()));
+    // Serve SSH key entries to SSH clients, asking before each use if configured
+    let mut ssh_requests = None;
+    if app_state.config.ssh_agent {
+        let keyring = Arc::new(ssh_agent::Keyring::default());
+        match ssh_agent::start(keyring.clone(), app_state.config.ssh_agent_confirm).await {
+            Ok(requests) => {
+                ssh_requests = requests;
+                app_state.ssh_keyring = Some(keyring);
+                load_ssh_keys(&mut stream, &mut app_state).await;
+            }
+            Err(e) => app_state.set_status(format!("Failed to start SSH agent: {}", e), true),
         }
     }
 
     // Main application loop
     loop {
+        // Lock the vault once it has been left idle long enough
+        let idle_lock_secs = app_state.config.idle_lock_secs;
+        if idle_lock_secs > 0
+            && app_state.input_mode != InputMode::Unlock
+            && app_state.last_activity.elapsed() >= Duration::from_secs(idle_lock_secs)
+        {
+            lock_vault(&mut app_state).await;
+        }
+
+        // Clear a copied value once it has been on the clipboard long enough
+        if clipboard_remaining(&app_state) == Some(0) {
+            clear_clipboard(&mut app_state);
+        }
+
+        // Ask about the next use of an SSH key once the last one is answered
+        if app_state.ssh_confirmation.is_none() {
+            if let Some(request) = ssh_requests
+                .as_mut()
+                .and_then(|requests| requests.try_recv().ok())
+            {
+                app_state.ssh_confirmation = Some(request);
+            }
+        }
+
+        // Drop the status message once it has been shown long enough
+        if let Some(status) = &app_state.status {
+            if status.shown_at.elapsed() >= STATUS_DURATION {
+                app_state.status = None;
+            }
+        }
+
         terminal.draw(|frame| {
             let size = frame.size();
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Min(3), Constraint::Length(3)].as_slice())
+                .constraints(
+                    [
+                        Constraint::Min(3),
+                        Constraint::Length(3),
+                        // Room for several lines while writing notes or keys
+                        Constraint::Length(if input_is_multiline(&app_state) {
+                            10
+                        } else {
+                            3
+                        }),
+                    ]
+                    .as_slice(),
+                )
                 .split(size);
 
-            let content = {
-                let mut display = String::from("Password Manager\n");
-                if app_state.input_mode == InputMode::Help {
-                    display.push_str("Available commands:\n");
-                    display.push_str("- s: Store a new password\n");
-                    display.push_str("- d: Delete a password\n");
-                    display.push_str("- g: Get password details\n");
-                    display.push_str("- f: Fetch password list\n");
-                    display.push_str("- n: Next page of passwords\n");
-                    display.push_str("- p: Previous page of passwords\n");
-                    display.push_str("- q: Quit the program\n");
-                    display.push_str("- h: Show this help\n");
-                } else if let Some(pw_info) = &app_state.current_password {
-                    let key = KEY.get().expect("Key not initialized");
-
-                    // Decrypt all fields
-                    let title = String::from_utf8(crypto::decrypt(pw_info.title.clone(), *key))
-                        .unwrap_or_else(|_| "Invalid UTF-8".to_string());
-                    let username =
-                        String::from_utf8(crypto::decrypt(pw_info.user_id.clone(), *key))
-                            .unwrap_or_else(|_| "Invalid UTF-8".to_string());
-                    let password =
-                        String::from_utf8(crypto::decrypt(pw_info.password.clone(), *key))
-                            .unwrap_or_else(|_| "Invalid UTF-8".to_string());
-                    let url = String::from_utf8(crypto::decrypt(pw_info.url.clone(), *key))
-                        .unwrap_or_else(|_| "Invalid UTF-8".to_string());
-
-                    display.push_str("\nPassword Details:\n");
-                    display.push_str(&format!("\nTitle: {}", title));
-                    display.push_str(&format!("\nUsername: {}", username));
-
-                    if app_state.show_password {
-                        display.push_str(&format!("\nPassword: {}", password));
-                    } else {
-                        display.push_str(&format!("\nPassword: {}", "*".repeat(password.len())));
-                    }
+            if let Some(request) = &app_state.ssh_confirmation {
+                let content = format!(
+                    "Password Manager\n\nAn SSH client wants to sign with the key \"{}\"\n\n\
+                     Press 'y' to allow or 'n' to refuse",
+                    request.title
+                );
+                let content = Paragraph::new(content).block(Block::default().borders(Borders::ALL));
+                frame.render_widget(content, chunks[0]);
+            } else if app_state.input_mode == InputMode::Search {
+                render_search(frame, &mut app_state, chunks[0]);
+            } else if list_view_active(&app_state) {
+                render_password_list(frame, &mut app_state, chunks[0]);
+            } else {
+                let content = {
+                    let mut display = String::from("Password Manager\n");
+                    if app_state.input_mode == InputMode::Unlock {
+                        display.push_str("\nLocked\n");
+                        display.push_str("\nEnter the master password to unlock, or Esc to quit\n");
+                    } else if app_state.input_mode == InputMode::Help {
+                        display.push_str("Available commands:\n");
+                        display.push_str("- s: Store a new entry\n");
+                        display.push_str("- Up/Down or j/k: Move through the password list\n");
+                        display.push_str("- Enter: Open the highlighted password\n");
+                        display.push_str("- e: Edit the highlighted or open password\n");
+                        display.push_str("- d: Delete the highlighted or open password\n");
+                        display.push_str("- g: Get password details by title\n");
+                        display.push_str("- f: Fetch password list and reload SSH keys\n");
+                        display.push_str("- t: View trash (r: restore, x: purge)\n");
+                        display.push_str("- v: View previous versions of the open password\n");
+                        display.push_str("- a: View passwords due for rotation\n");
+                        display.push_str("- /: Search passwords by title, URL, or username\n");
+                        display.push_str("- n/p: Move down/up a page of passwords\n");
+                        display.push_str("- o: Change password list ordering\n");
+                        display.push_str("- q: Quit the program\n");
+                        display.push_str(
+                            "- c-p/c-u: Copy the password/username of the selected password\n",
+                        );
+                        display.push_str("- c-t: Copy the one-time code of the open password\n");
+                        display.push_str("- c-h: Generate and copy the next HOTP code\n");
+                        display.push_str("- h: Show this help\n");
+                    } else if let Some(record) = &app_state.pending_delete {
+                        display.push_str("\nDelete this password?\n");
+                        display.push_str(&format!("\nTitle: {}", record.title));
+                        display.push_str(&format!("\nUsername: {}", record.user_id));
+                        display.push_str("\n\nEnter 'y' to delete or 'n' to cancel");
+                    } else if app_state.show_history {
+                        display.push_str("\nPrevious Versions:\n");
 
-                    display.push_str(&format!("\nURL: {}", url));
-                    display.push_str("\n\nPress 's' to show/hide password");
-                    display.push_str("\nPress 'c-p' to copy password");
-                    display.push_str("\nPress 'c-u' to copy username");
-                    display.push_str("\nPress Esc to return to password list");
-                } else {
-                    display.push_str("\nStored Passwords:\n");
+                        if app_state.history.is_empty() {
+                            display.push_str("\nNo previous versions.\n");
+                        } else {
+                            for (number, item) in app_state.history.iter().enumerate() {
+                                let password = if app_state.show_password {
+                                    item.record.password.clone()
+                                } else {
+                                    MASK.to_string()
+                                };
+                                display.push_str(&format!(
+                                    "\n{}. Replaced {}\n   Title: {}\n   Username: {}\n   \
+                                     Password: {}\n   URL: {}\n",
+                                    number + 1,
+                                    format_timestamp(item.replaced_at),
+                                    item.record.title,
+                                    item.record.user_id,
+                                    password,
+                                    item.record.url
+                                ));
+                            }
+                        }
+                        display.push_str("\nPress 's' to show/hide passwords");
+                        display.push_str("\nPress 'r' to restore a version");
+                        display.push_str("\nPress Esc to return to password details");
+                    } else if app_state.show_stale {
+                        display.push_str("\nPasswords Due for Rotation:\n");
 
-                    // Calculate total pages
-                    let total_pages = if app_state.password_list.is_empty() {
-                        1
-                    } else {
-                        (app_state.password_list.len() - 1) / app_state.items_per_page + 1
-                    };
+                        // Passwords past their maximum age, oldest first
+                        let mut stale: Vec<(&ListItem, Option<u64>, u64)> = app_state
+                            .password_list
+                            .iter()
+                            .filter(|item| item.kind == EntryKind::Login)
+                            .filter_map(|item| {
+                                let max_age = item
+                                    .max_age_days
+                                    .unwrap_or(app_state.config.max_password_age_days);
+                                let age = password_age_days(item.password_changed);
+                                match age {
+                                    Some(days) if days <= max_age => None,
+                                    _ => Some((item, age, max_age)),
+                                }
+                            })
+                            .collect();
+                        stale.sort_by_key(|(_, age, _)| std::cmp::Reverse(age.unwrap_or(u64::MAX)));
 
-                    // Get start and end indices for current page
-                    let start_idx = app_state.current_page * app_state.items_per_page;
-                    let end_idx =
-                        (start_idx + app_state.items_per_page).min(app_state.password_list.len());
-
-                    // Display page information
-                    display.push_str(&format!(
-                        "\nPage {} of {} ({} items)\n",
-                        app_state.current_page + 1,
-                        total_pages,
-                        app_state.password_list.len()
-                    ));
-
-                    // Display passwords for current page
-                    if app_state.password_list.is_empty() {
-                        display.push_str("\nNo passwords stored.\n");
-                    } else {
-                        for item in &app_state.password_list[start_idx..end_idx] {
-                            display
-                                .push_str(&format!("\nTitle: {}\nURL: {}\n", item.title, item.url));
+                        if stale.is_empty() {
+                            display.push_str("\nAll passwords are within their maximum age.\n");
+                        } else {
+                            for (item, age, max_age) in stale {
+                                let age = match age {
+                                    Some(days) => format!("{} days old", days),
+                                    None => "age unknown".to_string(),
+                                };
+                                display.push_str(&format!(
+                                    "\nTitle: {}\nLast changed: {} ({}, max {} days)\n",
+                                    item.title,
+                                    format_timestamp(item.password_changed),
+                                    age,
+                                    max_age
+                                ));
+                            }
+                        }
+                        display.push_str("\nPress Esc to return\n");
+                    } else if let Some(record) = &app_state.current_password {
+                        let kind = record.kind;
+                        let title = &record.title;
+                        let notes = &record.notes;
+
+                        if kind != EntryKind::Login {
+                            display.push_str(&format!("\n{}:\n", kind.label()));
+                            display.push_str(&format!("\nTitle: {}", title));
+
+                            for field in kind.schema() {
+                                let value =
+                                    record.fields.get(field.name).cloned().unwrap_or_default();
+                                let value = if field.hidden && !app_state.show_password {
+                                    MASK.to_string()
+                                } else {
+                                    value
+                                };
+                                if field.multiline {
+                                    display.push_str(&format!("\n{}:\n{}", field.label, value));
+                                } else {
+                                    display.push_str(&format!("\n{}: {}", field.label, value));
+                                }
+                            }
+                        } else {
+                            let username = &record.user_id;
+                            let password = &record.password;
+                            let url = &record.url;
+
+                            display.push_str("\nPassword Details:\n");
+                            display.push_str(&format!("\nTitle: {}", title));
+                            display.push_str(&format!("\nUsername: {}", username));
+
+                            if app_state.show_password {
+                                display.push_str(&format!("\nPassword: {}", password));
+                            } else {
+                                display.push_str(&format!("\nPassword: {}", MASK));
+                            }
+
+                            display.push_str(&format!("\nURL: {}", url));
+
+                            if let Some(totp) = &record.totp {
+                                let now = now_secs();
+                                match totp.code(now) {
+                                    Ok(code) => display.push_str(&format!(
+                                        "\nOne-time code: {} ({}s left)",
+                                        code,
+                                        totp.remaining(now)
+                                    )),
+                                    Err(e) => display.push_str(&format!("\nOne-time code: {}", e)),
+                                }
+                            }
+
+                            if let Some(hotp) = &record.hotp {
+                                let code =
+                                    app_state.hotp_code.as_deref().unwrap_or("not generated");
+                                display.push_str(&format!(
+                                    "\nHOTP code: {} (next counter {})",
+                                    code, hotp.counter
+                                ));
+                            }
+                        }
+
+                        if !notes.is_empty() {
+                            display.push_str("\n\nNotes:\n");
+                            display.push_str(notes);
+                        }
+
+                        if !record.custom_fields.is_empty() {
+                            display.push_str("\n\nCustom Fields:");
+                            for (number, field) in record.custom_fields.iter().enumerate() {
+                                let value = if field.field_type == FieldType::Hidden
+                                    && !app_state.show_password
+                                {
+                                    MASK.to_string()
+                                } else {
+                                    field.value.clone()
+                                };
+                                display.push_str(&format!(
+                                    "\n{}. {}: {}",
+                                    number + 1,
+                                    field.name,
+                                    value
+                                ));
+                            }
+                        }
+
+                        display.push_str(&format!(
+                            "\n\nCreated: {}",
+                            format_timestamp(record.created)
+                        ));
+                        display.push_str(&format!(
+                            "\nModified: {}",
+                            format_timestamp(record.modified)
+                        ));
+                        display.push_str(&format!(
+                            "\nLast used: {}",
+                            format_timestamp(record.last_used)
+                        ));
+
+                        if kind == EntryKind::Login {
+                            let password_changed = record.password_changed;
+                            let max_age = record
+                                .max_age_days
+                                .unwrap_or(app_state.config.max_password_age_days);
+                            let age = match password_age_days(password_changed) {
+                                Some(days) if days > max_age => {
+                                    format!("{} days old, overdue", days)
+                                }
+                                Some(days) => format!("{} days old", days),
+                                None => "age unknown".to_string(),
+                            };
+                            display.push_str(&format!(
+                                "\nPassword changed: {} ({}, max {} days)",
+                                format_timestamp(password_changed),
+                                age,
+                                max_age
+                            ));
+                            display.push_str("\n\nPress 's' to show/hide password");
+                            display.push_str("\nPress 'c-p' to copy password");
+                            display.push_str("\nPress 'c-u' to copy username");
+                            if record.totp.is_some() {
+                                display.push_str("\nPress 'c-t' to copy one-time code");
+                            }
+                            if record.hotp.is_some() {
+                                display.push_str("\nPress 'c-h' to copy the next HOTP code");
+                            }
+                        } else if kind.schema().iter().any(|field| field.hidden) {
+                            display.push_str("\n\nPress 's' to show/hide secrets");
+                            if let Some(name) = kind.primary_secret() {
+                                let label = kind
+                                    .schema()
+                                    .iter()
+                                    .find(|field| field.name == name)
+                                    .map_or(name, |field| field.label);
+                                display.push_str(&format!("\nPress 'c-p' to copy {}", label));
+                            }
+                        } else {
+                            display.push('\n');
                         }
+                        if !record.custom_fields.is_empty() {
+                            display.push_str("\nPress 'c-1' to 'c-9' to copy a custom field");
+                        }
+                        display.push_str("\nPress 'v' to view previous versions");
+                        display.push_str("\nPress Esc to return to password list");
+                    } else if let Some(title) = &app_state.pending_purge {
+                        display.push_str("\nPermanently purge this password?\n");
+                        display.push_str(&format!("\nTitle: {}", title));
+                        display.push_str("\n\nIt can't be restored afterwards.");
+                        display.push_str("\n\nEnter 'y' to purge or 'n' to cancel");
+                    } else if app_state.show_trash {
+                        display.push_str("\nTrash:\n");
 
-                        // Add navigation hints if multiple pages
-                        if total_pages > 1 {
-                            display.push_str("\nPress 'n' for next page, 'p' for previous page\n");
+                        if app_state.trash_list.is_empty() {
+                            display.push_str("\nTrash is empty.\n");
+                        } else {
+                            let now = now_secs();
+                            for item in &app_state.trash_list {
+                                let days_left =
+                                    item.expires_at.saturating_sub(now) / (24 * 60 * 60);
+                                display.push_str(&format!(
+                                    "\nTitle: {}\nURL: {}\nDeleted: {} (purged in {} days)\n",
+                                    item.title,
+                                    item.url,
+                                    format_timestamp(item.deleted_at),
+                                    days_left
+                                ));
+                            }
                         }
+                        display.push_str("\nPress 'r' to restore, 'x' to purge, Esc to return\n");
                     }
+                    display
+                };
+                let content = Paragraph::new(content).block(Block::default().borders(Borders::ALL));
+                frame.render_widget(content, chunks[0]);
+            }
+
+            // Status bar with the result of the last operation
+            let (status_text, status_style) = match &app_state.status {
+                Some(status) => {
+                    let color = if status.shown_at.elapsed() >= STATUS_FADE_AFTER {
+                        Color::DarkGray
+                    } else if status.is_error {
+                        Color::Red
+                    } else {
+                        Color::Green
+                    };
+                    (
+                        format!("[{}] {}", status.timestamp, status.text),
+                        Style::default().fg(color),
+                    )
                 }
-                display
+                None => (String::new(), Style::default()),
+            };
+            let status_title = match clipboard_remaining(&app_state) {
+                Some(secs) => format!("Status - clipboard clears in {}s", secs),
+                None => "Status".to_string(),
             };
-            let content = Paragraph::new(content).block(Block::default().borders(Borders::ALL));
-            frame.render_widget(content, chunks[0]);
+            let status_bar = Paragraph::new(status_text)
+                .style(status_style)
+                .block(Block::default().borders(Borders::ALL).title(status_title));
+            frame.render_widget(status_bar, chunks[1]);
 
             // Command input mini-buffer
+            let schema_prompt = match current_schema_field(&app_state) {
+                Some(field) if field.multiline => {
+                    format!(
+                        "Enter {} (Enter for a new line, Ctrl+S to finish):",
+                        field.label
+                    )
+                }
+                Some(field) => format!("Enter {}:", field.label),
+                None => String::new(),
+            };
             let input_prompt = match app_state.input_mode {
                 InputMode::Command => "Enter command (h for help):",
                 InputMode::Title => "Enter title:",
+                InputMode::EntryKindPrompt => {
+                    "Entry type: (l)ogin, secure (n)ote, (c)ard, (i)dentity, (s)sh key, \
+                     or (a)pi token:"
+                }
                 InputMode::UserId => "Enter username:",
                 InputMode::Password => "Enter password:",
                 InputMode::GeneratePasswordPrompt => {
@@ -407,51 +1803,211 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 }
                 InputMode::PasswordLengthPrompt => "Enter password length (recommended: 16-32):",
                 InputMode::Url => "Enter URL:",
+                InputMode::MaxAge => "Enter max password age in days (blank for default):",
+                InputMode::Totp => "Enter TOTP secret or otpauth:// URI (blank for none):",
+                InputMode::Hotp => "Enter HOTP secret or otpauth://hotp/ URI (blank for none):",
+                InputMode::SchemaField => schema_prompt.as_str(),
+                InputMode::Notes => "Enter notes (Enter for a new line, Ctrl+S to finish):",
+                InputMode::CustomFieldPrompt => "Add a custom field? (y/n):",
+                InputMode::CustomFieldName => "Enter custom field name:",
+                InputMode::CustomFieldType => "Field type: (t)ext, (h)idden, (u)rl, or (n)umber:",
+                InputMode::CustomFieldValue => "Enter custom field value:",
                 InputMode::Delete => "Enter title to delete:",
+                InputMode::ConfirmDelete => "Confirm delete (y/n):",
+                InputMode::Restore => "Enter title to restore:",
+                InputMode::Purge => "Enter title to purge permanently:",
+                InputMode::ConfirmPurge => "Confirm purge (y/n):",
+                InputMode::RestoreVersion => "Enter version number to restore:",
                 InputMode::Help => "Press any key to return",
                 InputMode::Get => "Enter title to view:",
+                InputMode::Search => "Search (Up/Down to move, Enter to open the highlighted one):",
+                InputMode::Unlock => "Enter master password:",
             };
-            let input = Paragraph::new(app_state.input.as_str())
+            // The master password is masked at a fixed width while it is typed
+            let input_text =
+                if app_state.input_mode == InputMode::Unlock && !app_state.input.is_empty() {
+                    MASK
+                } else {
+                    app_state.input.as_str()
+                };
+            let input = Paragraph::new(input_text)
                 .block(Block::default().borders(Borders::ALL).title(input_prompt));
-            frame.render_widget(input, chunks[1]);
+            frame.render_widget(input, chunks[2]);
         })?;
 
         // Handle input
         if event::poll(std::time::Duration::from_millis(100))? {
             if let Event::Key(key) = event::read()? {
+                app_state.last_activity = Instant::now();
+                let prompt = (app_state.input_mode, app_state.schema_field);
+                // Only the search query is kept, so typed secrets aren't copied on each key
+                let query =
+                    (app_state.input_mode == InputMode::Search).then(|| app_state.input.clone());
                 match key.code {
+                    // A pending use of an SSH key is answered before anything else
+                    KeyCode::Char(answer @ ('y' | 'n')) if app_state.ssh_confirmation.is_some() => {
+                        if let Some(request) = app_state.ssh_confirmation.take() {
+                            let title = request.title.clone();
+                            request.answer(answer == 'y');
+                            let verb = if answer == 'y' { "Allowed" } else { "Refused" };
+                            app_state.set_status(
+                                format!("{} use of SSH key \"{}\"", verb, title),
+                                false,
+                            );
+                        }
+                    }
+                    KeyCode::Esc if app_state.ssh_confirmation.is_some() => {
+                        if let Some(request) = app_state.ssh_confirmation.take() {
+                            request.answer(false);
+                        }
+                    }
+                    // Back out of the version list to the password it belongs to
+                    KeyCode::Esc
+                        if app_state.show_history && app_state.input_mode == InputMode::Command =>
+                    {
+                        app_state.show_history = false;
+                        app_state.history.clear();
+                    }
+                    // The only way out of the unlock prompt is to quit
+                    KeyCode::Esc if app_state.input_mode == InputMode::Unlock => {
+                        break;
+                    }
                     KeyCode::Esc => {
+                        app_state.show_history = false;
+                        app_state.history.clear();
+                        app_state.show_stale = false;
                         app_state.current_password = None;
+                        app_state.hotp_code = None;
+                        app_state.pending_delete = None;
+                        app_state.pending_purge = None;
                         app_state.show_password = false;
+                        app_state.editing = None;
+
+                        // Leave the trash view unless backing out of a trash prompt
+                        if !matches!(
+                            app_state.input_mode,
+                            InputMode::Restore | InputMode::Purge | InputMode::ConfirmPurge
+                        ) {
+                            app_state.show_trash = false;
+                        }
                         app_state.waiting_for_second_key = None;
 
                         if app_state.input_mode != InputMode::Command {
-                            app_state.input.clear();
-                            app_state.title.clear();
-                            app_state.user_id.clear();
-                            app_state.password.clear();
-                            app_state.url.clear();
+                            reset_entry_prompts(&mut app_state);
                             app_state.input_mode = InputMode::Command;
                         }
                     }
+                    // Move the highlight through the password list or the search results
+                    KeyCode::Up | KeyCode::Down
+                        if app_state.input_mode == InputMode::Search
+                            || (app_state.input_mode == InputMode::Command
+                                && list_view_active(&app_state)) =>
+                    {
+                        let offset = if key.code == KeyCode::Up { -1 } else { 1 };
+                        move_selection(&mut app_state, offset);
+                    }
+                    // Notes and keys take several lines, so Enter adds a line and Ctrl+S saves
+                    KeyCode::Enter if input_is_multiline(&app_state) => {
+                        app_state.input.push('\n');
+                    }
+                    KeyCode::Char('s')
+                        if app_state.input_mode == InputMode::SchemaField
+                            && key.modifiers.contains(KeyModifiers::CONTROL) =>
+                    {
+                        submit_schema_field(&mut app_state);
+                    }
+                    KeyCode::Char('s')
+                        if app_state.input_mode == InputMode::Notes
+                            && key.modifiers.contains(KeyModifiers::CONTROL) =>
+                    {
+                        app_state.notes = std::mem::take(&mut app_state.input);
+                        app_state.input_mode = InputMode::CustomFieldPrompt;
+                    }
                     KeyCode::Enter => {
                         match app_state.input_mode {
-                            InputMode::Command => {}
+                            InputMode::Command => {
+                                if let Some(title) = list_view_active(&app_state)
+                                    .then(|| selected_title(&app_state))
+                                    .flatten()
+                                {
+                                    open_entry(&mut stream, &mut app_state, &title).await;
+                                }
+                            }
                             InputMode::Delete => {
-                                let title = app_state.input.clone();
-                                let title_hash =
-                                    crypto::hash(crypto::HashInputType::Text(title.clone()));
+                                let title = std::mem::take(&mut app_state.input);
+                                app_state.input_mode = InputMode::Command;
+                                confirm_delete(&mut stream, &mut app_state, &title).await;
+                            }
+                            InputMode::ConfirmDelete => {
+                                let answer = app_state.input.to_lowercase();
+                                app_state.input.clear();
 
-                                if let Ok(_) = send(&mut stream, 5, &title_hash[0..32]).await {
-                                    let _ = update_password_list(&mut stream, &mut app_state).await;
+                                if answer == "y" {
+                                    if let Some(record) = app_state.pending_delete.take() {
+                                        let title = record.title.clone();
+                                        match request(&mut stream, 5, &record.title_hash()).await {
+                                            Ok(_) => {
+                                                app_state.set_status(
+                                                    format!("Deleted \"{}\"", title),
+                                                    false,
+                                                );
+                                                if let Err(e) = update_password_list(
+                                                    &mut stream,
+                                                    &mut app_state,
+                                                )
+                                                .await
+                                                {
+                                                    app_state.set_status(
+                                                        format!(
+                                                            "Failed to refresh password list: {}",
+                                                            e
+                                                        ),
+                                                        true,
+                                                    );
+                                                }
+                                            }
+                                            Err(e) => app_state
+                                                .set_status(format!("Delete failed: {}", e), true),
+                                        }
+                                    }
+                                    app_state.input_mode = InputMode::Command;
+                                } else if answer == "n" {
+                                    app_state.pending_delete = None;
+                                    app_state.set_status("Delete cancelled", false);
+                                    app_state.input_mode = InputMode::Command;
                                 }
-                                app_state.input.clear();
-                                app_state.input_mode = InputMode::Command;
                             }
                             InputMode::Title => {
                                 app_state.title = app_state.input.clone();
                                 app_state.input.clear();
-                                app_state.input_mode = InputMode::UserId;
+                                app_state.input_mode = InputMode::EntryKindPrompt;
+                            }
+                            InputMode::EntryKindPrompt => {
+                                match app_state.input.to_lowercase().as_str() {
+                                    "l" => {
+                                        app_state.entry_kind = EntryKind::Login;
+                                        app_state.input_mode = InputMode::UserId;
+                                    }
+                                    "n" => {
+                                        // Secure notes skip straight to the notes editor
+                                        app_state.entry_kind = EntryKind::SecureNote;
+                                        app_state.input_mode = InputMode::Notes;
+                                    }
+                                    answer @ ("c" | "i" | "s" | "a") => {
+                                        // Other kinds fill in the fields from their schema
+                                        app_state.entry_kind = match answer {
+                                            "c" => EntryKind::Card,
+                                            "i" => EntryKind::Identity,
+                                            "s" => EntryKind::SshKey,
+                                            _ => EntryKind::ApiToken,
+                                        };
+                                        app_state.fields.clear();
+                                        app_state.schema_field = 0;
+                                        app_state.input_mode = InputMode::SchemaField;
+                                    }
+                                    _ => {}
+                                }
+                                app_state.input.clear();
                             }
                             InputMode::UserId => {
                                 app_state.user_id = app_state.input.clone();
@@ -474,8 +2030,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 }
                             }
                             InputMode::Password => {
-                                app_state.password = app_state.input.clone();
-                                app_state.input.clear();
+                                // Move the buffer rather than copy it, so no stray copy is left
+                                app_state.password = std::mem::take(&mut app_state.input);
                                 app_state.input_mode = InputMode::Url;
                             }
                             InputMode::PasswordLengthPrompt => {
@@ -498,46 +2054,261 @@ async fn main() -> Result<(), Box<dyn Error>> {
                             }
                             InputMode::Url => {
                                 app_state.url = app_state.input.clone();
-                                if let Ok(json) = wrap_password(
-                                    app_state.title.clone(),
-                                    app_state.user_id.clone(),
-                                    app_state.password.clone(),
-                                    app_state.url.clone(),
-                                ) {
-                                    if let Ok(_) = send(&mut stream, 1, &json).await {
-                                        let _ =
-                                            update_password_list(&mut stream, &mut app_state).await;
+                                app_state.input.clear();
+                                app_state.input_mode = InputMode::MaxAge;
+                            }
+                            InputMode::MaxAge => {
+                                let max_age_days = if app_state.input.trim().is_empty() {
+                                    None
+                                } else if let Ok(days) = app_state.input.trim().parse::<u64>() {
+                                    Some(days)
+                                } else {
+                                    // Invalid input, clear and stay in the same mode
+                                    app_state.input.clear();
+                                    continue;
+                                };
+
+                                app_state.max_age_days = max_age_days;
+                                app_state.input.clear();
+                                app_state.input_mode = InputMode::Totp;
+                            }
+                            InputMode::Totp => {
+                                if app_state.input.trim().is_empty() {
+                                    app_state.totp = None;
+                                } else {
+                                    match totp::Totp::parse(&app_state.input) {
+                                        Ok(totp) => app_state.totp = Some(totp),
+                                        Err(e) => {
+                                            // Invalid input, report it and stay in the same mode
+                                            app_state.set_status(e, true);
+                                            app_state.input.clear();
+                                            continue;
+                                        }
                                     }
                                 }
                                 app_state.input.clear();
-                                app_state.title.clear();
-                                app_state.user_id.clear();
-                                app_state.password.clear();
-                                app_state.url.clear();
+
+                                // Entries with a TOTP secret don't need a counter-based one too
+                                app_state.input_mode = if app_state.totp.is_some() {
+                                    InputMode::Notes
+                                } else {
+                                    InputMode::Hotp
+                                };
+                            }
+                            InputMode::Hotp => {
+                                if app_state.input.trim().is_empty() {
+                                    app_state.hotp = None;
+                                } else {
+                                    match totp::Hotp::parse(&app_state.input) {
+                                        Ok(hotp) => app_state.hotp = Some(hotp),
+                                        Err(e) => {
+                                            // Invalid input, report it and stay in the same mode
+                                            app_state.set_status(e, true);
+                                            app_state.input.clear();
+                                            continue;
+                                        }
+                                    }
+                                }
+                                app_state.input.clear();
+                                app_state.input_mode = InputMode::Notes;
+                            }
+                            InputMode::SchemaField => submit_schema_field(&mut app_state),
+                            // Enter adds a new line while writing notes
+                            InputMode::Notes => {}
+                            InputMode::CustomFieldPrompt => {
+                                let answer = app_state.input.to_lowercase();
+                                app_state.input.clear();
+
+                                if answer == "y" {
+                                    app_state.input_mode = InputMode::CustomFieldName;
+                                } else if answer == "n" {
+                                    save_entered_password(&mut stream, &mut app_state).await;
+                                }
+                            }
+                            InputMode::CustomFieldName => {
+                                if !app_state.input.is_empty() {
+                                    app_state.custom_field_name =
+                                        std::mem::take(&mut app_state.input);
+                                    app_state.input_mode = InputMode::CustomFieldType;
+                                }
+                            }
+                            InputMode::CustomFieldType => {
+                                let field_type = match app_state.input.to_lowercase().as_str() {
+                                    "t" => Some(FieldType::Text),
+                                    "h" => Some(FieldType::Hidden),
+                                    "u" => Some(FieldType::Url),
+                                    "n" => Some(FieldType::Number),
+                                    _ => None,
+                                };
+                                app_state.input.clear();
+
+                                if let Some(field_type) = field_type {
+                                    app_state.custom_field_type = field_type;
+                                    app_state.input_mode = InputMode::CustomFieldValue;
+                                }
+                            }
+                            InputMode::CustomFieldValue => {
+                                // Number fields must hold a valid number
+                                if app_state.custom_field_type == FieldType::Number
+                                    && app_state.input.trim().parse::<f64>().is_err()
+                                {
+                                    app_state.input.clear();
+                                    continue;
+                                }
+
+                                let field = CustomField {
+                                    name: std::mem::take(&mut app_state.custom_field_name),
+                                    field_type: app_state.custom_field_type,
+                                    value: std::mem::take(&mut app_state.input),
+                                };
+                                app_state.custom_fields.push(field);
+                                app_state.input_mode = InputMode::CustomFieldPrompt;
+                            }
+                            InputMode::Restore => {
+                                let title = std::mem::take(&mut app_state.input);
                                 app_state.input_mode = InputMode::Command;
+                                trash_request(&mut stream, &mut app_state, &title, 7, "Restore")
+                                    .await;
                             }
-                            InputMode::Help => {
+                            InputMode::Purge => {
+                                let title = std::mem::take(&mut app_state.input);
                                 app_state.input_mode = InputMode::Command;
+                                confirm_purge(&mut app_state, title);
                             }
-                            InputMode::Get => {
-                                let title = app_state.input.clone();
-                                let title_hash = crypto::hash(crypto::HashInputType::Text(title));
-
-                                // Send get request (type 2) with the title hash
-                                if let Ok(_) = send(&mut stream, 2, &title_hash).await {
-                                    if let Ok((response_type, data)) = receive(&mut stream).await {
-                                        if response_type == 2 {
-                                            if let Ok(pw_info) =
-                                                serde_json::from_slice::<PasswordInfo>(&data)
+                            InputMode::ConfirmPurge => {
+                                let answer = app_state.input.to_lowercase();
+                                app_state.input.clear();
+
+                                if answer == "y" {
+                                    if let Some(title) = app_state.pending_purge.take() {
+                                        trash_request(
+                                            &mut stream,
+                                            &mut app_state,
+                                            &title,
+                                            8,
+                                            "Purge",
+                                        )
+                                        .await;
+                                    }
+                                    app_state.input_mode = InputMode::Command;
+                                } else if answer == "n" {
+                                    app_state.pending_purge = None;
+                                    app_state.set_status("Purge cancelled", false);
+                                    app_state.input_mode = InputMode::Command;
+                                }
+                            }
+                            InputMode::RestoreVersion => {
+                                let selected = app_state
+                                    .input
+                                    .parse::<usize>()
+                                    .ok()
+                                    .and_then(|number| number.checked_sub(1))
+                                    .and_then(|position| app_state.history.get(position))
+                                    .map(|item| item.index);
+                                let title_hash =
+                                    app_state.current_password.as_ref().map(Record::title_hash);
+                                app_state.input.clear();
+                                app_state.input_mode = InputMode::Command;
+
+                                if let (Some(index), Some(title_hash)) = (selected, title_hash) {
+                                    let mut data = title_hash.to_vec();
+                                    data.extend_from_slice(&index.to_be_bytes());
+
+                                    let result = match request(&mut stream, 10, &data).await {
+                                        Ok(_) => request(&mut stream, 2, &title_hash).await,
+                                        Err(e) => Err(e),
+                                    };
+                                    match result {
+                                        Ok(data) => {
+                                            app_state.hotp_code = None;
+                                            app_state.show_history = false;
+                                            app_state.history.clear();
+                                            match open_record(&data) {
+                                                Ok(record) => {
+                                                    app_state.current_password = Some(record);
+                                                    app_state.set_status("Version restored", false);
+                                                }
+                                                Err(e) => {
+                                                    app_state.current_password = None;
+                                                    app_state.set_status(
+                                                        format!(
+                                                            "Restored, but can't open it: {}",
+                                                            e
+                                                        ),
+                                                        true,
+                                                    );
+                                                }
+                                            }
+                                            if let Err(e) =
+                                                update_password_list(&mut stream, &mut app_state)
+                                                    .await
                                             {
-                                                app_state.current_password = Some(pw_info);
+                                                app_state.set_status(
+                                                    format!(
+                                                        "Failed to refresh password list: {}",
+                                                        e
+                                                    ),
+                                                    true,
+                                                );
                                             }
                                         }
+                                        Err(e) => app_state
+                                            .set_status(format!("Restore failed: {}", e), true),
                                     }
+                                } else {
+                                    app_state.set_status("No such version", true);
                                 }
-                                app_state.input.clear();
+                            }
+                            InputMode::Help => {
+                                app_state.input_mode = InputMode::Command;
+                            }
+                            InputMode::Unlock => {
+                                let password = Zeroizing::new(std::mem::take(&mut app_state.input));
+                                match validate_password(&password, &mut stream).await {
+                                    Ok(Some(key)) => {
+                                        match KEY.unlock(&key) {
+                                            Ok(_) => app_state.set_status("Unlocked", false),
+                                            Err(e) => app_state.set_status(
+                                                format!("Unlocked, but key not pinned: {}", e),
+                                                true,
+                                            ),
+                                        }
+                                        let _ = agent::add_key(&key).await;
+                                        app_state.input_mode = InputMode::Command;
+                                        if let Err(e) =
+                                            update_password_list(&mut stream, &mut app_state).await
+                                        {
+                                            app_state.set_status(
+                                                format!("Failed to load password list: {}", e),
+                                                true,
+                                            );
+                                        }
+                                        load_ssh_keys(&mut stream, &mut app_state).await;
+                                    }
+                                    Ok(None) => app_state.set_status("Invalid password", true),
+                                    Err(e) => {
+                                        app_state.set_status(format!("Unlock failed: {}", e), true)
+                                    }
+                                }
+                            }
+                            InputMode::Get => {
+                                let title = std::mem::take(&mut app_state.input);
+                                open_entry(&mut stream, &mut app_state, &title).await;
                                 app_state.input_mode = InputMode::Command;
                             }
+                            InputMode::Search => {
+                                let selected =
+                                    selected_item(&app_state).map(|item| item.title.clone());
+                                match selected {
+                                    Some(title) => {
+                                        app_state.input.clear();
+                                        app_state.input_mode = InputMode::Command;
+                                        restore_selection(&mut app_state, Some(title.clone()));
+                                        open_entry(&mut stream, &mut app_state, &title).await;
+                                    }
+                                    None => app_state.set_status("No matching passwords", true),
+                                }
+                            }
                         }
                     }
                     KeyCode::Char(c) => {
@@ -546,29 +2317,117 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 // Handle second key of combination
                                 match (first_key, c) {
                                     ('c', 'p') => {
-                                        if let Some(pw_info) = &app_state.current_password {
-                                            let key = KEY.get().expect("Key not initialized");
-                                            if let Ok(decrypted) = String::from_utf8(
-                                                crypto::decrypt(pw_info.password.clone(), *key),
-                                            ) {
-                                                if let Ok(mut ctx) = ClipboardContext::new() {
-                                                    let _ = ctx.set_contents(decrypted);
+                                        if let Some(record) =
+                                            copy_source(&mut stream, &mut app_state).await
+                                        {
+                                            let kind = record.kind;
+                                            match kind.primary_secret() {
+                                                Some(name) => {
+                                                    let value = record
+                                                        .fields
+                                                        .get(name)
+                                                        .cloned()
+                                                        .unwrap_or_default();
+                                                    copy_to_clipboard(
+                                                        &mut app_state,
+                                                        value,
+                                                        kind.label(),
+                                                    );
+                                                }
+                                                None if kind == EntryKind::Login => {
+                                                    copy_to_clipboard(
+                                                        &mut app_state,
+                                                        record.password.clone(),
+                                                        "Password",
+                                                    )
                                                 }
+                                                // Notes and identities keep no password
+                                                None => app_state.set_status(
+                                                    format!(
+                                                        "{} has no password to copy",
+                                                        kind.label()
+                                                    ),
+                                                    true,
+                                                ),
                                             }
                                         }
                                     }
                                     ('c', 'u') => {
-                                        if let Some(pw_info) = &app_state.current_password {
-                                            let key = KEY.get().expect("Key not initialized");
-                                            if let Ok(decrypted) = String::from_utf8(
-                                                crypto::decrypt(pw_info.user_id.clone(), *key),
-                                            ) {
-                                                if let Ok(mut ctx) = ClipboardContext::new() {
-                                                    let _ = ctx.set_contents(decrypted);
+                                        if let Some(record) =
+                                            copy_source(&mut stream, &mut app_state).await
+                                        {
+                                            copy_to_clipboard(
+                                                &mut app_state,
+                                                record.user_id.clone(),
+                                                "Username",
+                                            );
+                                        }
+                                    }
+                                    ('c', 't') => {
+                                        let code = copy_source(&mut stream, &mut app_state)
+                                            .await
+                                            .and_then(|record| {
+                                                record
+                                                    .totp
+                                                    .as_ref()
+                                                    .map(|totp| totp.code(now_secs()))
+                                            });
+                                        match code {
+                                            Some(Ok(code)) => copy_to_clipboard(
+                                                &mut app_state,
+                                                code,
+                                                "One-time code",
+                                            ),
+                                            Some(Err(e)) => app_state.set_status(e, true),
+                                            None => app_state.set_status(
+                                                "No one-time code for this entry",
+                                                true,
+                                            ),
+                                        }
+                                    }
+                                    ('c', 'h') => {
+                                        // Work on a copy so a failed save leaves the counter as is
+                                        if let Some(mut record) =
+                                            copy_source(&mut stream, &mut app_state).await
+                                        {
+                                            match next_hotp_code(&mut stream, &mut record).await {
+                                                Ok(code) => {
+                                                    if app_state.current_password.is_some() {
+                                                        app_state.current_password = Some(record);
+                                                        app_state.hotp_code = Some(code.clone());
+                                                    }
+                                                    copy_to_clipboard(
+                                                        &mut app_state,
+                                                        code,
+                                                        "HOTP code",
+                                                    );
                                                 }
+                                                Err(e) => app_state.set_status(
+                                                    format!("Failed to generate HOTP code: {}", e),
+                                                    true,
+                                                ),
                                             }
                                         }
                                     }
+                                    ('c', '1'..='9') => {
+                                        let position = c as usize - '1' as usize;
+                                        let field = copy_source(&mut stream, &mut app_state)
+                                            .await
+                                            .and_then(|record| {
+                                                record.custom_fields.get(position).cloned()
+                                            });
+                                        match field {
+                                            Some(field) => copy_to_clipboard(
+                                                &mut app_state,
+                                                field.value.clone(),
+                                                &field.name,
+                                            ),
+                                            None => app_state.set_status(
+                                                format!("No custom field {}", position + 1),
+                                                true,
+                                            ),
+                                        }
+                                    }
                                     _ => {}
                                 }
                                 app_state.waiting_for_second_key = None;
@@ -576,7 +2435,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 // Handle first key press
                                 match c {
                                     'c' => {
-                                        if app_state.current_password.is_some() {
+                                        if target_title(&app_state).is_some() {
                                             app_state.waiting_for_second_key = Some('c');
                                         }
                                     }
@@ -587,41 +2446,122 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                             app_state.input_mode = InputMode::Title;
                                         }
                                     }
-                                    'n' => {
-                                        if app_state.current_password.is_none() {
-                                            let total_pages = if app_state.password_list.is_empty()
+                                    'j' if list_view_active(&app_state) => {
+                                        move_selection(&mut app_state, 1);
+                                    }
+                                    'k' if list_view_active(&app_state) => {
+                                        move_selection(&mut app_state, -1);
+                                    }
+                                    'n' if list_view_active(&app_state) => {
+                                        let offset = app_state.items_per_page as isize;
+                                        move_selection(&mut app_state, offset);
+                                    }
+                                    'p' if list_view_active(&app_state) => {
+                                        let offset = app_state.items_per_page as isize;
+                                        move_selection(&mut app_state, -offset);
+                                    }
+                                    'd' => match target_title(&app_state) {
+                                        Some(title) => {
+                                            confirm_delete(&mut stream, &mut app_state, &title)
+                                                .await
+                                        }
+                                        None => app_state.input_mode = InputMode::Delete,
+                                    },
+                                    'e' => {
+                                        if let Some(title) = target_title(&app_state) {
+                                            start_edit(&mut stream, &mut app_state, &title).await;
+                                        }
+                                    }
+                                    'g' => {
+                                        app_state.input_mode = InputMode::Get;
+                                    }
+                                    't' => {
+                                        app_state.current_password = None;
+                                        app_state.show_password = false;
+                                        app_state.show_history = false;
+                                        app_state.history.clear();
+                                        app_state.show_stale = false;
+                                        match update_trash_list(&mut stream, &mut app_state).await {
+                                            Ok(_) => app_state.show_trash = true,
+                                            Err(e) => app_state.set_status(
+                                                format!("Failed to load trash: {}", e),
+                                                true,
+                                            ),
+                                        }
+                                    }
+                                    'r' if app_state.show_trash => {
+                                        app_state.input_mode = InputMode::Restore;
+                                    }
+                                    'r' if app_state.show_history => {
+                                        app_state.input_mode = InputMode::RestoreVersion;
+                                    }
+                                    'v' => {
+                                        if let Some(title_hash) = app_state
+                                            .current_password
+                                            .as_ref()
+                                            .map(Record::title_hash)
+                                        {
+                                            match update_history(
+                                                &mut stream,
+                                                &mut app_state,
+                                                &title_hash,
+                                            )
+                                            .await
                                             {
-                                                1
-                                            } else {
-                                                (app_state.password_list.len() - 1)
-                                                    / app_state.items_per_page
-                                                    + 1
-                                            };
-                                            if app_state.current_page + 1 < total_pages {
-                                                app_state.current_page += 1;
+                                                Ok(_) => app_state.show_history = true,
+                                                Err(e) => app_state.set_status(
+                                                    format!("Failed to load versions: {}", e),
+                                                    true,
+                                                ),
                                             }
                                         }
                                     }
-                                    'p' => {
-                                        if app_state.current_password.is_none()
-                                            && app_state.current_page > 0
+                                    'x' if app_state.show_trash => {
+                                        app_state.input_mode = InputMode::Purge;
+                                    }
+                                    'f' => {
+                                        match update_password_list(&mut stream, &mut app_state)
+                                            .await
                                         {
-                                            app_state.current_page -= 1;
+                                            Ok(_) => app_state
+                                                .set_status("Password list refreshed", false),
+                                            Err(e) => app_state.set_status(
+                                                format!("Failed to refresh password list: {}", e),
+                                                true,
+                                            ),
                                         }
+                                        // Pick up SSH keys added or changed since they were loaded
+                                        load_ssh_keys(&mut stream, &mut app_state).await;
                                     }
-                                    'd' => {
-                                        app_state.input_mode = InputMode::Delete;
+                                    'a' => {
+                                        app_state.current_password = None;
+                                        app_state.show_password = false;
+                                        app_state.show_trash = false;
+                                        app_state.show_history = false;
+                                        app_state.history.clear();
+                                        app_state.show_stale = true;
                                     }
-                                    'g' => {
-                                        app_state.input_mode = InputMode::Get;
-                                    }
-                                    'f' => {
-                                        let _ =
-                                            update_password_list(&mut stream, &mut app_state).await;
+                                    'o' => {
+                                        app_state.sort_mode = app_state.sort_mode.next();
+                                        sort_password_list(&mut app_state);
+                                        app_state.set_status(
+                                            format!("Sorted by {}", app_state.sort_mode.label()),
+                                            false,
+                                        );
                                     }
                                     'h' => {
                                         app_state.input_mode = InputMode::Help;
                                     }
+                                    '/' => {
+                                        app_state.current_password = None;
+                                        app_state.show_password = false;
+                                        app_state.show_trash = false;
+                                        app_state.show_history = false;
+                                        app_state.history.clear();
+                                        app_state.show_stale = false;
+                                        app_state.search_state.select(Some(0));
+                                        app_state.input_mode = InputMode::Search;
+                                    }
                                     'q' => {
                                         break;
                                     }
@@ -639,11 +2579,31 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     }
                     _ => {}
                 }
+
+                // A changed query starts the highlight over at the best match
+                if app_state.input_mode == InputMode::Search
+                    && query.is_some_and(|query| query != app_state.input)
+                {
+                    app_state.search_state.select(Some(0));
+                }
+
+                // Fill in each new prompt with the value of the entry being edited
+                if (app_state.input_mode, app_state.schema_field) != prompt
+                    && app_state.input.is_empty()
+                {
+                    if let Some(value) = edit_prefill(&app_state) {
+                        app_state.input = value;
+                    }
+                }
             }
         }
     }
 
-    // Cleanup
+    // Cleanup, without leaving a copied secret behind
+    clear_clipboard(&mut app_state);
+    if app_state.ssh_keyring.is_some() {
+        ssh_agent::stop();
+    }
     disable_raw_mode()?;
     terminal.backend_mut().execute(LeaveAlternateScreen)?;
 
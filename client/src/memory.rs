@@ -0,0 +1,81 @@
+/*
+ * ----------------------------------------------------------------------------
+ * Project:     Personal Password Manager
+ * File:        memory.rs
+ * Description: Keeps key material out of swap and the client's memory out of
+ *              core dumps.
+ *
+ * Author:      RoscoeEH
+ * ---------------------------------------------------------------------------
+ */
+
+use std::error::Error;
+
+// Locks the pages holding a value into RAM so they are never written to swap.
+// If the limit on locked memory is too low the value still works, it just isn't
+// pinned, so callers report the error rather than giving up.
+#[cfg(unix)]
+pub fn lock<T: ?Sized>(value: &T) -> Result<(), Box<dyn Error>> {
+    let address = value as *const T as *const libc::c_void;
+    if unsafe { libc::mlock(address, std::mem::size_of_val(value)) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn lock<T: ?Sized>(_value: &T) -> Result<(), Box<dyn Error>> {
+    Ok(())
+}
+
+// Releases pages locked by lock, once the value in them has been wiped. This
+// unlocks whole pages, so the value should have its pages to itself.
+#[cfg(unix)]
+pub fn unlock<T: ?Sized>(value: &T) {
+    let address = value as *const T as *const libc::c_void;
+    unsafe {
+        libc::munlock(address, std::mem::size_of_val(value));
+    }
+}
+
+#[cfg(not(unix))]
+pub fn unlock<T: ?Sized>(_value: &T) {}
+
+// Size of a memory page, the unit that lock and unlock work in
+#[cfg(unix)]
+pub fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        size if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn page_size() -> usize {
+    4096
+}
+
+// Stops the process from writing core dumps, which would hold the session key
+// and any decrypted entries
+#[cfg(unix)]
+pub fn disable_core_dumps() -> Result<(), Box<dyn Error>> {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    // Also keeps other processes of the same user from attaching to read memory
+    #[cfg(target_os = "linux")]
+    if unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn disable_core_dumps() -> Result<(), Box<dyn Error>> {
+    Ok(())
+}
@@ -0,0 +1,169 @@
+/*
+ * ----------------------------------------------------------------------------
+ * Project:     Personal Password Manager
+ * File:        reference.rs
+ * Description: References to fields of stored entries, written as
+ *              pm://<title>/<field>, and the env files and templates they are
+ *              used in.
+ *
+ * Author:      RoscoeEH
+ * ---------------------------------------------------------------------------
+ */
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use zeroize::Zeroizing;
+
+const SCHEME: &str = "pm://";
+
+// A field of a stored entry, named by the entry's title
+#[derive(Debug, PartialEq)]
+pub struct Reference {
+    pub title: String,
+    pub field: String,
+}
+
+impl Reference {
+    // Parses a whole value as a reference, or gives None if it isn't one. The field
+    // follows the last slash, so titles may contain slashes.
+    pub fn parse(value: &str) -> Result<Option<Reference>, String> {
+        let Some(path) = value.strip_prefix(SCHEME) else {
+            return Ok(None);
+        };
+        match path.rsplit_once('/') {
+            Some((title, field)) if !title.is_empty() && !field.is_empty() => Ok(Some(Reference {
+                title: title.to_string(),
+                field: field.to_string(),
+            })),
+            _ => Err(format!(
+                "Invalid reference \"{}\", expected {}<title>/<field>",
+                value, SCHEME
+            )),
+        }
+    }
+}
+
+// A piece of a template: text to copy as it is, or a reference to fill in
+pub enum Part<'a> {
+    Text(&'a str),
+    Reference(Reference),
+}
+
+// Splits a template into text and {{ pm://<title>/<field> }} references. Other
+// {{ }} blocks are left as text, so templates meant for other tools still work.
+pub fn parse_template(template: &str) -> Result<Vec<Part<'_>>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(length) = rest[start..].find("}}") else {
+            break;
+        };
+        let end = start + length + 2;
+        let inner = rest[start + 2..end - 2].trim();
+
+        if inner.starts_with(SCHEME) {
+            let reference = Reference::parse(inner).map_err(|e| {
+                let offset = template.len() - rest.len() + start;
+                let line = template[..offset].matches('\n').count() + 1;
+                format!("Line {}: {}", line, e)
+            })?;
+            parts.push(Part::Text(&rest[..start]));
+            parts.extend(reference.map(Part::Reference));
+        } else {
+            parts.push(Part::Text(&rest[..end]));
+        }
+        rest = &rest[end..];
+    }
+    parts.push(Part::Text(rest));
+    Ok(parts)
+}
+
+// Variables in a dotenv-style file: NAME=value lines, skipping blank lines and
+// # comments, with an optional "export " before the name and optional quotes
+// around the value. A name given twice takes the later value.
+pub fn parse_env_file(
+    contents: &str,
+) -> Result<BTreeMap<String, Zeroizing<String>>, Box<dyn Error>> {
+    let mut variables = BTreeMap::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("Line {} of the env file isn't NAME=value", index + 1))?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!(
+                "Invalid variable name on line {} of the env file",
+                index + 1
+            )
+            .into());
+        }
+
+        variables.insert(
+            name.to_string(),
+            Zeroizing::new(unquote(value.trim()).to_string()),
+        );
+    }
+    Ok(variables)
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+#[test]
+fn references_and_env_files() {
+    assert_eq!(
+        Reference::parse("pm://work/GitHub/password"),
+        Ok(Some(Reference {
+            title: "work/GitHub".to_string(),
+            field: "password".to_string(),
+        }))
+    );
+    assert_eq!(Reference::parse("postgres://localhost"), Ok(None));
+    assert!(Reference::parse("pm://GitHub").is_err());
+    assert!(Reference::parse("pm://GitHub/").is_err());
+
+    let contents = "# Local services\n\nexport DB_PASSWORD=\"pm://db/password\"\nPORT=8080\n";
+    let variables = parse_env_file(contents).expect("Env file should parse");
+    assert_eq!(variables.len(), 2);
+    assert_eq!(*variables["DB_PASSWORD"], "pm://db/password");
+    assert_eq!(*variables["PORT"], "8080");
+
+    assert!(parse_env_file("NOT A VARIABLE").is_err());
+}
+
+#[test]
+fn template_parts() {
+    let template = "user: {{ pm://db/username }}\npass: {{pm://db/password}}\nkeep: {{ .Values }}";
+    let parts = parse_template(template).expect("Template should parse");
+    let rendered: String = parts
+        .iter()
+        .map(|part| match part {
+            Part::Text(text) => text.to_string(),
+            Part::Reference(reference) => format!("<{}.{}>", reference.title, reference.field),
+        })
+        .collect();
+    assert_eq!(
+        rendered,
+        "user: <db.username>\npass: <db.password>\nkeep: {{ .Values }}"
+    );
+
+    let error = parse_template("ok\n{{ pm://db }}").err();
+    assert_eq!(
+        error.as_deref(),
+        Some("Line 2: Invalid reference \"pm://db\", expected pm://<title>/<field>")
+    );
+}
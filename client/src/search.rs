@@ -0,0 +1,115 @@
+/*
+ * ----------------------------------------------------------------------------
+ * Project:     Personal Password Manager
+ * File:        search.rs
+ * Description: Fuzzy matching used to filter and rank the decrypted password
+ *              list as a search query is typed.
+ *
+ * Author:      RoscoeEH
+ * ---------------------------------------------------------------------------
+ */
+
+// Score adjustments for matched characters
+const MATCH_SCORE: i64 = 1;
+const CONSECUTIVE_BONUS: i64 = 5;
+const WORD_START_BONUS: i64 = 3;
+const GAP_PENALTY: i64 = 1;
+
+// Score taken off matches in each field after the first, so title matches rank highest
+const FIELD_PENALTY: i64 = 10;
+
+// A field that matched, with the character positions to highlight
+pub struct Match {
+    pub score: i64,
+    pub positions: Vec<usize>,
+}
+
+// An entry that matched the query, with its highlights for each searched field
+pub struct Ranked {
+    pub index: usize,
+    pub score: i64,
+    pub positions: Vec<Vec<usize>>,
+}
+
+// Matches the query's characters in order anywhere in the text, ignoring case
+pub fn fuzzy_match(query: &str, text: &str) -> Option<Match> {
+    let text: Vec<char> = text.chars().collect();
+    let mut positions: Vec<usize> = Vec::new();
+    let mut score = 0;
+    let mut next = 0;
+
+    for query_char in query.chars().filter(|c| !c.is_whitespace()) {
+        let query_char = query_char.to_lowercase().next()?;
+        let found =
+            (next..text.len()).find(|&i| text[i].to_lowercase().next() == Some(query_char))?;
+
+        score += MATCH_SCORE;
+        match positions.last() {
+            Some(&last) if last + 1 == found => score += CONSECUTIVE_BONUS,
+            Some(&last) => score -= GAP_PENALTY * (found - last - 1).min(3) as i64,
+            None => score -= GAP_PENALTY * found.min(3) as i64,
+        }
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += WORD_START_BONUS;
+        }
+
+        positions.push(found);
+        next = found + 1;
+    }
+
+    Some(Match { score, positions })
+}
+
+// Ranks entries by their best matching field, best first, dropping ones that don't match.
+// Each entry gives its searchable fields in order of importance.
+pub fn rank(query: &str, entries: &[Vec<&str>]) -> Vec<Ranked> {
+    let mut ranked: Vec<Ranked> = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(index, fields)| {
+            let matches: Vec<Option<Match>> = fields
+                .iter()
+                .map(|field| fuzzy_match(query, field))
+                .collect();
+            let score = matches
+                .iter()
+                .enumerate()
+                .filter_map(|(position, found)| {
+                    found
+                        .as_ref()
+                        .map(|found| found.score - FIELD_PENALTY * position as i64)
+                })
+                .max()?;
+
+            Some(Ranked {
+                index,
+                score,
+                positions: matches
+                    .into_iter()
+                    .map(|found| found.map(|found| found.positions).unwrap_or_default())
+                    .collect(),
+            })
+        })
+        .collect();
+
+    ranked.sort_by_key(|entry| std::cmp::Reverse(entry.score));
+    ranked
+}
+
+#[test]
+fn fuzzy_ranking() {
+    let entries = vec![
+        vec!["GitHub", "https://github.com"],
+        vec!["Gmail", "https://mail.google.com"],
+        vec!["Bank", "https://example.com/gh"],
+    ];
+
+    let ranked = rank("gh", &entries);
+    let order: Vec<usize> = ranked.iter().map(|entry| entry.index).collect();
+    assert_eq!(order, vec![0, 2]);
+    assert_eq!(ranked[0].positions[0], vec![0, 3]);
+
+    assert!(fuzzy_match("mail", "Gmail").is_some());
+    assert!(fuzzy_match("liam", "Gmail").is_none());
+    assert_eq!(rank("", &entries).len(), 3);
+}
@@ -0,0 +1,153 @@
+/*
+ * ----------------------------------------------------------------------------
+ * Project:     Personal Password Manager
+ * File:        session.rs
+ * Description: Holds the key derived from the master password while the vault
+ *              is unlocked, and wipes it when the vault locks.
+ *
+ * Author:      RoscoeEH
+ * ---------------------------------------------------------------------------
+ */
+
+use std::alloc::{self, Layout};
+use std::cell::Cell;
+use std::error::Error;
+use std::ptr::NonNull;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use zeroize::Zeroize;
+
+use crate::memory;
+
+// Key kept in memory that is locked out of swap, and overwritten when dropped. It
+// gets a page to itself, so unlocking that page can't unpin anything else.
+struct LockedKey {
+    key: NonNull<[u8; 32]>,
+    layout: Layout,
+}
+
+// The key is owned like a Box, and only reached through the session's mutex
+unsafe impl Send for LockedKey {}
+
+impl LockedKey {
+    // A zeroed key at the start of its own page
+    fn new() -> Self {
+        let page_size = memory::page_size().max(std::mem::size_of::<[u8; 32]>());
+        let layout = Layout::from_size_align(page_size, page_size).expect("Invalid page size");
+        let key = unsafe { alloc::alloc_zeroed(layout) } as *mut [u8; 32];
+        match NonNull::new(key) {
+            Some(key) => LockedKey { key, layout },
+            None => alloc::handle_alloc_error(layout),
+        }
+    }
+
+    fn get(&self) -> &[u8; 32] {
+        unsafe { self.key.as_ref() }
+    }
+
+    fn get_mut(&mut self) -> &mut [u8; 32] {
+        unsafe { self.key.as_mut() }
+    }
+}
+
+impl Drop for LockedKey {
+    fn drop(&mut self) {
+        self.get_mut().zeroize();
+        memory::unlock(self.get());
+        unsafe { alloc::dealloc(self.key.as_ptr() as *mut u8, self.layout) };
+    }
+}
+
+thread_local! {
+    // Set while this thread has the session key lent out to a closure
+    static LENDING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Marks the session key as lent out until dropped, even if the closure panics
+struct Lending;
+
+impl Lending {
+    fn start() -> Self {
+        LENDING.with(|lending| lending.set(true));
+        Lending
+    }
+}
+
+impl Drop for Lending {
+    fn drop(&mut self) {
+        LENDING.with(|lending| lending.set(false));
+    }
+}
+
+// Key for the current session, None while the vault is locked
+#[derive(Default)]
+pub struct SessionKey {
+    key: Mutex<Option<LockedKey>>,
+}
+
+impl SessionKey {
+    pub const fn new() -> Self {
+        SessionKey {
+            key: Mutex::new(None),
+        }
+    }
+
+    // A panic elsewhere can't leave the key half written, so a poisoned lock is still usable
+    fn slot(&self) -> MutexGuard<'_, Option<LockedKey>> {
+        // Waiting on the lock held by with on this same thread would never return
+        assert!(
+            !LENDING.with(Cell::get),
+            "Session key used while lent out by SessionKey::with"
+        );
+        self.key.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Copies in the key derived from the master password, unlocking the vault. The
+    // vault unlocks either way; an error means the key couldn't be kept out of swap.
+    pub fn unlock(&self, key: &[u8; 32]) -> Result<(), Box<dyn Error>> {
+        // Lock the page before the key is copied into it
+        let mut locked = LockedKey::new();
+        let pinned = memory::lock(locked.get());
+        locked.get_mut().copy_from_slice(key);
+        *self.slot() = Some(locked);
+        pinned
+    }
+
+    // Overwrites the key, locking the vault until the master password is entered again
+    pub fn lock(&self) {
+        *self.slot() = None;
+    }
+
+    // Runs f with the session key, or gives None while the vault is locked. The key is
+    // lent rather than copied out, and f must not use the session key itself.
+    pub fn with<R>(&self, f: impl FnOnce(&[u8; 32]) -> R) -> Option<R> {
+        let slot = self.slot();
+        let key = slot.as_ref()?;
+        let _lending = Lending::start();
+        Some(f(key.get()))
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.slot().is_some()
+    }
+}
+
+#[test]
+fn lock_wipes_key() {
+    let session = SessionKey::new();
+    assert!(!session.is_unlocked());
+
+    let _ = session.unlock(&[7; 32]);
+    assert_eq!(session.with(|key| *key), Some([7; 32]));
+
+    session.lock();
+    assert!(!session.is_unlocked());
+    assert_eq!(session.with(|key| *key), None);
+}
+
+#[test]
+#[should_panic(expected = "lent out")]
+fn nested_use_panics() {
+    let session = SessionKey::new();
+    let _ = session.unlock(&[7; 32]);
+    session.with(|_| session.is_unlocked());
+}
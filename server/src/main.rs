@@ -12,54 +12,151 @@
 use hex;
 use std::error::Error;
 use std::str;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
+use tokio::sync::Mutex;
 
 use rocksdb::DB;
 
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 static STORAGE_PATH: &str = "password_map";
 static FULL_LIST: &str = "accounts_list";
+static TRASH_LIST: &str = "trash_list";
+static TRASH_PREFIX: &str = "trash_";
+static HISTORY_PREFIX: &str = "history_";
 
+// Deleted passwords stay in the trash for 30 days before being purged
+const TRASH_RETENTION_SECS: u64 = 30 * 24 * 60 * 60;
+
+// Number of previous revisions kept for each password
+const MAX_REVISIONS: usize = 10;
+
+// Largest message accepted from a client
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+// Only the fields the server needs to index a password; the rest of the stored
+// JSON, including the encrypted record, is kept as sent
 #[derive(Serialize, Deserialize)]
 struct PasswordInfo {
     title_hash: [u8; 32],
+    #[serde(default)]
+    summary: Vec<u8>,
+    // Encrypted title and URL of passwords stored before summaries existed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     title: Vec<u8>,
-    user_id: Vec<u8>,
-    password: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     url: Vec<u8>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct ListItem {
     title_hash: [u8; 32],
+    #[serde(default)]
+    summary: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    title: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    url: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TrashItem {
+    title_hash: [u8; 32],
+    #[serde(default)]
+    summary: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     title: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     url: Vec<u8>,
+    deleted_at: u64,
+    expires_at: u64,
+}
+
+// A previous version of a password, kept as the encrypted JSON it was stored as
+#[derive(Serialize, Deserialize)]
+struct Revision {
+    replaced_at: u64,
+    record: String,
+}
+
+// Current time in seconds since the Unix epoch
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Reads a serialized list from the database, treating a missing key as empty
+fn load_list<T: DeserializeOwned>(
+    db: &DB,
+    key: &str,
+) -> Result<Vec<T>, Box<dyn Error + Send + Sync>> {
+    match db.get(key)? {
+        Some(list_data) => Ok(serde_json::from_slice(&list_data)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn store_password(db: &DB, pw_json: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    save_password(db, pw_json, true)
 }
 
-fn store_password(pw_json: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let db = DB::open_default(STORAGE_PATH)?;
+// Replaces an existing password without keeping the old version, for metadata-only changes
+fn update_password(db: &DB, pw_json: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let pw_info: PasswordInfo = serde_json::from_slice(pw_json)?;
+
+    if db.get(hex::encode(pw_info.title_hash))?.is_none() {
+        return Err(Box::from("Password not found"));
+    }
+    save_password(db, pw_json, false)
+}
+
+// Stores a password, optionally keeping the version it replaces in the history
+fn save_password(
+    db: &DB,
+    pw_json: &[u8],
+    keep_history: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let pw_info: PasswordInfo = serde_json::from_slice(pw_json)?;
     let title_hash_str = hex::encode(pw_info.title_hash);
 
+    // Keep the previous version before it is overwritten
+    let previous = if keep_history {
+        db.get(&title_hash_str)?
+    } else {
+        None
+    };
+    if let Some(previous) = previous {
+        let history_key = format!("{}{}", HISTORY_PREFIX, title_hash_str);
+        let mut history: Vec<Revision> = load_list(db, &history_key)?;
+        history.push(Revision {
+            replaced_at: now_secs(),
+            record: String::from_utf8(previous)?,
+        });
+        if history.len() > MAX_REVISIONS {
+            history.drain(..history.len() - MAX_REVISIONS);
+        }
+        db.put(&history_key, serde_json::to_vec(&history)?)?;
+    }
+
     // Store the full encrypted password info
     db.put(&title_hash_str, pw_json)?;
 
-    // Create a reduced version for the list with just title and URL
+    // Create a reduced version for the list with just the encrypted summary
     let list_item = ListItem {
         title_hash: pw_info.title_hash,
+        summary: pw_info.summary,
         title: pw_info.title,
         url: pw_info.url,
     };
 
-    // Update the full list with just titles and URLs
-    let mut full_list: Vec<ListItem> = match db.get(FULL_LIST)? {
-        Some(list_data) => serde_json::from_slice(&list_data)?,
-        None => Vec::new(),
-    };
-
+    // Update the full list with the reduced entries, replacing any older entry
+    let mut full_list: Vec<ListItem> = load_list(db, FULL_LIST)?;
+    full_list.retain(|item| item.title_hash != list_item.title_hash);
     full_list.push(list_item);
     let updated_list = serde_json::to_vec(&full_list)?;
     db.put(FULL_LIST, updated_list)?;
@@ -67,24 +164,47 @@ fn store_password(pw_json: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
     Ok(())
 }
 
-fn get_password(pw_id: &[u8]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-    // Open the RocksDB database
-    let db = DB::open_default(STORAGE_PATH)?;
+// Gets the stored previous versions of a password, oldest first
+fn get_history(db: &DB, title_hash: &[u8]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let history_key = format!("{}{}", HISTORY_PREFIX, hex::encode(title_hash));
+
+    let history: Vec<Revision> = load_list(db, &history_key)?;
+    Ok(serde_json::to_vec(&history)?)
+}
+
+// Makes a previous version current again, keeping the replaced version in the history
+fn restore_revision(db: &DB, data: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if data.len() != 36 {
+        return Err(Box::from("Invalid restore request"));
+    }
+    let (title_hash, index) = data.split_at(32);
+    let index = u32::from_be_bytes(index.try_into()?) as usize;
 
+    let title_hash_str = hex::encode(title_hash);
+    if db.get(&title_hash_str)?.is_none() {
+        return Err(Box::from("Password not found"));
+    }
+
+    let history_key = format!("{}{}", HISTORY_PREFIX, title_hash_str);
+    let history: Vec<Revision> = load_list(db, &history_key)?;
+    let revision = history
+        .get(index)
+        .ok_or_else(|| Box::<dyn Error + Send + Sync>::from("Version not found"))?;
+
+    save_password(db, revision.record.as_bytes(), true)
+}
+
+// Gets the stored JSON of a password, or None if there is no password with this hash
+fn get_password(db: &DB, pw_id: &[u8]) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
     // Convert the pw_id from bytes to a hex string
     let pw_id_str = hex::encode(pw_id);
 
     // Retrieve the full JSON stored under the title_hash key
-    match db.get(&pw_id_str)? {
-        Some(value) => Ok(value), // Return the full JSON as Vec<u8>
-        None => Err(Box::from("Password not found")),
-    }
+    Ok(db.get(&pw_id_str)?)
 }
 
-// Gets a list of passwords with a hash, title, and url
-fn get_list() -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-    let db = DB::open_default(STORAGE_PATH)?;
-
+// Gets a list of passwords with a hash and encrypted summary
+fn get_list(db: &DB) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
     match db.get(FULL_LIST)? {
         Some(value) => Ok(value),
         None => {
@@ -94,41 +214,162 @@ fn get_list() -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
     }
 }
 
-fn delete_password(title_hash: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let db = DB::open_default(STORAGE_PATH)?;
+// Moves a password into the trash instead of removing it outright
+fn delete_password(db: &DB, title_hash: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
     let title_hash_str = hex::encode(title_hash);
 
-    // Update the full list by removing password
-    let mut full_list: Vec<ListItem> = match db.get(FULL_LIST)? {
-        Some(list_data) => serde_json::from_slice(&list_data)?,
-        None => Vec::new(),
+    // Report missing entries instead of silently succeeding
+    let pw_json = match db.get(&title_hash_str)? {
+        Some(value) => value,
+        None => return Err(Box::from("Password not found")),
     };
+    let pw_info: PasswordInfo = serde_json::from_slice(&pw_json)?;
 
+    // Update the full list by removing password
+    let mut full_list: Vec<ListItem> = load_list(db, FULL_LIST)?;
     full_list.retain(|item| item.title_hash != *title_hash);
 
     // Store the updated list
     let updated_list = serde_json::to_vec(&full_list)?;
     db.put(FULL_LIST, updated_list)?;
 
-    // Delete the password entry
+    // Add the password to the trash, replacing any older trashed copy
+    let deleted_at = now_secs();
+    let mut trash_list: Vec<TrashItem> = load_list(db, TRASH_LIST)?;
+    trash_list.retain(|item| item.title_hash != *title_hash);
+    trash_list.push(TrashItem {
+        title_hash: pw_info.title_hash,
+        summary: pw_info.summary,
+        title: pw_info.title,
+        url: pw_info.url,
+        deleted_at,
+        expires_at: deleted_at + TRASH_RETENTION_SECS,
+    });
+    db.put(TRASH_LIST, serde_json::to_vec(&trash_list)?)?;
+
+    // Move the password entry into the trash namespace
+    db.put(format!("{}{}", TRASH_PREFIX, title_hash_str), &pw_json)?;
     db.delete(&title_hash_str)?;
 
+    purge_expired_trash(db)
+}
+
+// Gets the list of trashed passwords with a hash, encrypted summary and deletion times
+fn get_trash_list(db: &DB) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    purge_expired_trash(db)?;
+
+    let trash_list: Vec<TrashItem> = load_list(db, TRASH_LIST)?;
+    Ok(serde_json::to_vec(&trash_list)?)
+}
+
+// Moves a password from the trash back into the active list
+fn restore_password(db: &DB, title_hash: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let title_hash_str = hex::encode(title_hash);
+    let trash_key = format!("{}{}", TRASH_PREFIX, title_hash_str);
+
+    if db.get(&title_hash_str)?.is_some() {
+        return Err(Box::from("A password with this title already exists"));
+    }
+    let pw_json = match db.get(&trash_key)? {
+        Some(value) => value,
+        None => return Err(Box::from("Password not found in trash")),
+    };
+    let pw_info: PasswordInfo = serde_json::from_slice(&pw_json)?;
+
+    // Put the entry back in the full list
+    let mut full_list: Vec<ListItem> = load_list(db, FULL_LIST)?;
+    full_list.push(ListItem {
+        title_hash: pw_info.title_hash,
+        summary: pw_info.summary,
+        title: pw_info.title,
+        url: pw_info.url,
+    });
+    db.put(FULL_LIST, serde_json::to_vec(&full_list)?)?;
+    db.put(&title_hash_str, &pw_json)?;
+
+    // Remove it from the trash
+    let mut trash_list: Vec<TrashItem> = load_list(db, TRASH_LIST)?;
+    trash_list.retain(|item| item.title_hash != *title_hash);
+    db.put(TRASH_LIST, serde_json::to_vec(&trash_list)?)?;
+    db.delete(&trash_key)?;
+
+    Ok(())
+}
+
+// Permanently removes a password from the trash
+fn purge_password(db: &DB, title_hash: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let trash_key = format!("{}{}", TRASH_PREFIX, hex::encode(title_hash));
+
+    if db.get(&trash_key)?.is_none() {
+        return Err(Box::from("Password not found in trash"));
+    }
+
+    let mut trash_list: Vec<TrashItem> = load_list(db, TRASH_LIST)?;
+    trash_list.retain(|item| item.title_hash != *title_hash);
+    db.put(TRASH_LIST, serde_json::to_vec(&trash_list)?)?;
+    db.delete(&trash_key)?;
+    db.delete(format!("{}{}", HISTORY_PREFIX, hex::encode(title_hash)))?;
+
+    Ok(())
+}
+
+// Permanently removes trashed passwords whose retention period has passed
+fn purge_expired_trash(db: &DB) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let now = now_secs();
+    let trash_list: Vec<TrashItem> = load_list(db, TRASH_LIST)?;
+    let (expired, kept): (Vec<TrashItem>, Vec<TrashItem>) = trash_list
+        .into_iter()
+        .partition(|item| item.expires_at <= now);
+
+    if expired.is_empty() {
+        return Ok(());
+    }
+
+    for item in &expired {
+        let title_hash_str = hex::encode(item.title_hash);
+        db.delete(format!("{}{}", TRASH_PREFIX, title_hash_str))?;
+        db.delete(format!("{}{}", HISTORY_PREFIX, title_hash_str))?;
+    }
+    db.put(TRASH_LIST, serde_json::to_vec(&kept)?)?;
+
     Ok(())
 }
 
-// Send data to the client
+// Send data to the client, prefixed with the response type and data length
 async fn send(
     socket: &mut tokio::net::TcpStream,
     request_type: u8,
     data: &[u8],
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let mut response = vec![request_type];
+    response.extend_from_slice(&(data.len() as u32).to_be_bytes());
     response.extend_from_slice(data);
 
     socket.write_all(&response).await?;
     Ok(())
 }
 
+// Read one request from the client, returning None once the client disconnects
+async fn receive(
+    socket: &mut tokio::net::TcpStream,
+) -> Result<Option<(u8, Vec<u8>)>, Box<dyn Error + Send + Sync>> {
+    let mut header = [0; 5];
+    match socket.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if length > MAX_MESSAGE_LEN {
+        return Err(Box::from("Request too large"));
+    }
+
+    let mut data = vec![0; length];
+    socket.read_exact(&mut data).await?;
+    Ok(Some((header[0], data)))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     // Assign address to localhost
@@ -138,13 +379,22 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let tcp_listener = TcpListener::bind(&address).await?;
     println!("Server running on {}", address);
 
+    // Open the database once and share it across connections, one request at a time
+    let db = Arc::new(Mutex::new(DB::open_default(STORAGE_PATH)?));
+
+    // Clear out anything that expired in the trash while the server was down
+    if let Err(e) = purge_expired_trash(&*db.lock().await) {
+        println!("Failed to purge expired trash: {}", e);
+    }
+
     loop {
         // Wait for inbound socket
         let (mut socket, addr) = tcp_listener.accept().await?;
 
         // Spawn async task
+        let db = Arc::clone(&db);
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(&mut socket, addr).await {
+            if let Err(e) = handle_connection(&db, &mut socket, addr).await {
                 println!("Error handling connection from {}: {}", addr, e);
             }
         });
@@ -152,95 +402,330 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
 }
 
 async fn handle_connection(
+    db: &Mutex<DB>,
     socket: &mut tokio::net::TcpStream,
     addr: std::net::SocketAddr,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut buffer = vec![0; 1024];
-
     loop {
-        let n = match socket.read(&mut buffer).await {
-            Ok(0) => {
+        let (request, data) = match receive(socket).await {
+            Ok(Some(message)) => message,
+            Ok(None) => {
                 break;
             }
-            Ok(n) => n,
             Err(e) => {
                 println!("Failed to read from socket: {}. Error: {}", addr, e);
                 break;
             }
         };
-
-        if n < 1 {
-            continue;
+        if request == 4 {
+            send(socket, 4, b"Session closed").await?;
+            break;
         }
 
-        let request = buffer[0];
-        let data = if request == 5 {
-            // Take exactly 32 bytes
-            &buffer[1..33]
-        } else {
-            &buffer[1..n]
-        };
+        // Hold the database only while the request runs, not while a slow client is sent
+        // the response
+        let (response_type, response) = respond(&*db.lock().await, request, &data);
+        send(socket, response_type, &response).await?;
+    }
+    Ok(())
+}
 
-        match request {
-            1 => {
-                // Find the end of the JSON data
-                let json_end = data
-                    .iter()
-                    .position(|&x| x == b'}')
-                    .map(|p| p + 1)
-                    .unwrap_or(data.len());
-
-                let clean_data = &data[..json_end];
-
-                match serde_json::from_slice::<PasswordInfo>(clean_data) {
-                    Ok(_) => match store_password(clean_data) {
-                        Ok(_) => send(socket, 1, b"").await?,
-                        Err(_) => send(socket, 0, b"Store failed").await?,
-                    },
-                    Err(e) => {
-                        println!("JSON parsing error: {}", e);
-                        send(socket, 0, b"Invalid JSON format").await?
-                    }
-                }
-            }
-            2 => match get_password(data) {
-                Ok(password) => send(socket, 2, &password).await?,
-                Err(e) => {
-                    println!("Failed to get password: {}", e);
-                    send(socket, 0, b"Password not found").await?
-                }
+// Runs a request against the database, giving the response type and data to send
+fn respond(db: &DB, request: u8, data: &[u8]) -> (u8, Vec<u8>) {
+    match request {
+        1 => match serde_json::from_slice::<PasswordInfo>(data) {
+            Ok(_) => match store_password(db, data) {
+                Ok(_) => (1, Vec::new()),
+                Err(_) => (0, b"Store failed".to_vec()),
             },
-            3 => {
-                match get_list() {
-                    Ok(item_list) => {
-                        // Deserialize to count items
-                        if let Ok(_) = serde_json::from_slice::<Vec<ListItem>>(&item_list) {}
-                        send(socket, 3, &item_list).await?
-                    }
-                    Err(e) => {
-                        println!("Failed to get item list: {}", e);
-                        // Send empty list
-                        let empty_list = serde_json::to_vec(&Vec::<Value>::new())?;
-                        send(socket, 3, &empty_list).await?
-                    }
-                }
+            Err(e) => {
+                println!("JSON parsing error: {}", e);
+                (0, b"Invalid JSON format".to_vec())
             }
-            4 => {
-                send(socket, 4, b"Session closed").await?;
-                break;
+        },
+        2 => match get_password(db, data) {
+            Ok(Some(password)) => (2, password),
+            // Empty data tells the client there is no such password, unlike an error
+            Ok(None) => (2, Vec::new()),
+            Err(e) => {
+                println!("Failed to get password: {}", e);
+                (0, b"Failed to read password".to_vec())
             }
-            5 => match delete_password(data) {
-                Ok(_) => send(socket, 5, b"Password deleted").await?,
+        },
+        3 => {
+            match get_list(db) {
+                Ok(item_list) => {
+                    // Deserialize to count items
+                    if let Ok(_) = serde_json::from_slice::<Vec<ListItem>>(&item_list) {}
+                    (3, item_list)
+                }
+                // An empty list would pass for a new vault that takes any master password
                 Err(e) => {
-                    println!("Failed to delete password: {}", e);
-                    send(socket, 0, b"Delete failed").await?
+                    println!("Failed to get item list: {}", e);
+                    (0, b"Failed to read password list".to_vec())
                 }
-            },
-            _ => {
-                println!("Unknown request type: {}", request);
-                send(socket, 0, b"Unknown request").await?;
             }
         }
+        5 => match delete_password(db, data) {
+            Ok(_) => (5, b"Password deleted".to_vec()),
+            Err(e) => {
+                println!("Failed to delete password: {}", e);
+                (0, e.to_string().into_bytes())
+            }
+        },
+        6 => match get_trash_list(db) {
+            Ok(trash_list) => (6, trash_list),
+            Err(e) => {
+                println!("Failed to get trash list: {}", e);
+                (0, b"Failed to read trash".to_vec())
+            }
+        },
+        7 => match restore_password(db, data) {
+            Ok(_) => (7, b"Password restored".to_vec()),
+            Err(e) => {
+                println!("Failed to restore password: {}", e);
+                (0, e.to_string().into_bytes())
+            }
+        },
+        8 => match purge_password(db, data) {
+            Ok(_) => (8, b"Password purged".to_vec()),
+            Err(e) => {
+                println!("Failed to purge password: {}", e);
+                (0, e.to_string().into_bytes())
+            }
+        },
+        9 => match get_history(db, data) {
+            Ok(history) => (9, history),
+            Err(e) => {
+                println!("Failed to get history: {}", e);
+                (0, b"Failed to read history".to_vec())
+            }
+        },
+        10 => match restore_revision(db, data) {
+            Ok(_) => (10, b"Version restored".to_vec()),
+            Err(e) => {
+                println!("Failed to restore version: {}", e);
+                (0, e.to_string().into_bytes())
+            }
+        },
+        11 => match update_password(db, data) {
+            Ok(_) => (11, Vec::new()),
+            Err(e) => {
+                println!("Failed to update password: {}", e);
+                (0, e.to_string().into_bytes())
+            }
+        },
+        _ => {
+            println!("Unknown request type: {}", request);
+            (0, b"Unknown request".to_vec())
+        }
     }
-    Ok(())
+}
+
+// Runs f against a fresh database that is removed afterwards
+#[cfg(test)]
+fn with_test_db(name: &str, f: impl FnOnce(&DB)) {
+    let path = std::env::temp_dir().join(format!("pm_server_test_{}_{}", name, std::process::id()));
+    let _ = DB::destroy(&rocksdb::Options::default(), &path);
+    {
+        let db = DB::open_default(&path).unwrap();
+        f(&db);
+    }
+    DB::destroy(&rocksdb::Options::default(), &path).unwrap();
+}
+
+// Stored JSON for a password whose title hash is filled with byte, marked with a version
+#[cfg(test)]
+fn test_password(byte: u8, version: u8) -> Vec<u8> {
+    serde_json::to_vec(&PasswordInfo {
+        title_hash: [byte; 32],
+        summary: vec![version],
+        title: Vec::new(),
+        url: Vec::new(),
+    })
+    .unwrap()
+}
+
+#[test]
+fn trash_restore_and_purge() {
+    with_test_db("trash", |db| {
+        store_password(db, &test_password(1, 0)).unwrap();
+        store_password(db, &test_password(1, 1)).unwrap();
+        store_password(db, &test_password(2, 0)).unwrap();
+
+        // Deleting moves the password out of the list and into the trash
+        delete_password(db, &[1; 32]).unwrap();
+        assert!(get_password(db, &[1; 32]).unwrap().is_none());
+        let list: Vec<ListItem> = load_list(db, FULL_LIST).unwrap();
+        assert_eq!(list.len(), 1);
+        let trash: Vec<TrashItem> = serde_json::from_slice(&get_trash_list(db).unwrap()).unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(
+            trash[0].expires_at,
+            trash[0].deleted_at + TRASH_RETENTION_SECS
+        );
+
+        // Restoring brings back the password and its history
+        restore_password(db, &[1; 32]).unwrap();
+        assert_eq!(
+            get_password(db, &[1; 32]).unwrap(),
+            Some(test_password(1, 1))
+        );
+        let history: Vec<Revision> =
+            serde_json::from_slice(&get_history(db, &[1; 32]).unwrap()).unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(load_list::<TrashItem>(db, TRASH_LIST).unwrap().is_empty());
+        assert!(restore_password(db, &[1; 32]).is_err());
+
+        // Purging removes the password and its history for good
+        delete_password(db, &[1; 32]).unwrap();
+        purge_password(db, &[1; 32]).unwrap();
+        assert!(load_list::<TrashItem>(db, TRASH_LIST).unwrap().is_empty());
+        assert!(db
+            .get(format!("{}{}", TRASH_PREFIX, hex::encode([1; 32])))
+            .unwrap()
+            .is_none());
+        assert_eq!(get_history(db, &[1; 32]).unwrap(), b"[]");
+        assert!(restore_password(db, &[1; 32]).is_err());
+        assert!(purge_password(db, &[1; 32]).is_err());
+    });
+}
+
+#[test]
+fn expired_trash_is_purged() {
+    with_test_db("expired", |db| {
+        store_password(db, &test_password(1, 0)).unwrap();
+        store_password(db, &test_password(2, 0)).unwrap();
+        delete_password(db, &[1; 32]).unwrap();
+        delete_password(db, &[2; 32]).unwrap();
+
+        // Backdate the first password so its retention period has passed
+        let mut trash: Vec<TrashItem> = load_list(db, TRASH_LIST).unwrap();
+        for item in trash.iter_mut().filter(|item| item.title_hash == [1; 32]) {
+            item.deleted_at -= TRASH_RETENTION_SECS + 1;
+            item.expires_at = item.deleted_at + TRASH_RETENTION_SECS;
+        }
+        db.put(TRASH_LIST, serde_json::to_vec(&trash).unwrap())
+            .unwrap();
+
+        let trash: Vec<TrashItem> = serde_json::from_slice(&get_trash_list(db).unwrap()).unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].title_hash, [2; 32]);
+        assert!(db
+            .get(format!("{}{}", TRASH_PREFIX, hex::encode([1; 32])))
+            .unwrap()
+            .is_none());
+        assert!(db
+            .get(format!("{}{}", TRASH_PREFIX, hex::encode([2; 32])))
+            .unwrap()
+            .is_some());
+        assert!(restore_password(db, &[1; 32]).is_err());
+        restore_password(db, &[2; 32]).unwrap();
+    });
+}
+
+#[test]
+fn history_keeps_latest_revisions() {
+    with_test_db("history", |db| {
+        let versions = MAX_REVISIONS as u8 + 3;
+        for version in 0..versions {
+            store_password(db, &test_password(1, version)).unwrap();
+        }
+
+        // Only the most recently replaced versions are kept, oldest first
+        let history: Vec<Revision> =
+            serde_json::from_slice(&get_history(db, &[1; 32]).unwrap()).unwrap();
+        let kept: Vec<Vec<u8>> = history
+            .iter()
+            .map(|r| r.record.clone().into_bytes())
+            .collect();
+        let expected: Vec<Vec<u8>> = (versions - 1 - MAX_REVISIONS as u8..versions - 1)
+            .map(|version| test_password(1, version))
+            .collect();
+        assert_eq!(kept, expected);
+
+        // Metadata-only updates replace the password without adding to the history
+        update_password(db, &test_password(1, 100)).unwrap();
+        assert_eq!(
+            get_password(db, &[1; 32]).unwrap(),
+            Some(test_password(1, 100))
+        );
+        let history: Vec<Revision> =
+            serde_json::from_slice(&get_history(db, &[1; 32]).unwrap()).unwrap();
+        assert_eq!(history.len(), MAX_REVISIONS);
+        assert!(update_password(db, &test_password(2, 0)).is_err());
+    });
+}
+
+#[test]
+fn restore_revision_keeps_replaced_version() {
+    with_test_db("restore_revision", |db| {
+        store_password(db, &test_password(1, 0)).unwrap();
+        store_password(db, &test_password(1, 1)).unwrap();
+
+        let mut request = [1; 32].to_vec();
+        request.extend_from_slice(&0u32.to_be_bytes());
+        restore_revision(db, &request).unwrap();
+        assert_eq!(
+            get_password(db, &[1; 32]).unwrap(),
+            Some(test_password(1, 0))
+        );
+
+        // The version that was current before the restore can itself be restored
+        let history: Vec<Revision> =
+            serde_json::from_slice(&get_history(db, &[1; 32]).unwrap()).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].record.as_bytes(), test_password(1, 1).as_slice());
+
+        // The list shows the restored version
+        let list: Vec<ListItem> = load_list(db, FULL_LIST).unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].summary, vec![0]);
+
+        // Unknown versions, unknown passwords and malformed requests are refused
+        request[32..].copy_from_slice(&2u32.to_be_bytes());
+        assert!(restore_revision(db, &request).is_err());
+        assert!(restore_revision(db, &[2; 36]).is_err());
+        assert!(restore_revision(db, &[1; 35]).is_err());
+    });
+}
+
+#[tokio::test]
+async fn messages_keep_their_framing() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+
+    // Messages sent back to back are read one at a time, whatever their size
+    let large = vec![7; 100_000];
+    send(&mut client, 1, b"first").await.unwrap();
+    send(&mut client, 2, b"").await.unwrap();
+    send(&mut client, 3, &large).await.unwrap();
+    assert_eq!(
+        receive(&mut server).await.unwrap(),
+        Some((1, b"first".to_vec()))
+    );
+    assert_eq!(receive(&mut server).await.unwrap(), Some((2, Vec::new())));
+    assert_eq!(receive(&mut server).await.unwrap(), Some((3, large)));
+
+    // A closed connection ends the session rather than failing it
+    drop(client);
+    assert_eq!(receive(&mut server).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn oversized_requests_are_rejected() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+
+    // The length is checked before any of the data is read
+    let mut header = vec![1];
+    header.extend_from_slice(&(MAX_MESSAGE_LEN as u32 + 1).to_be_bytes());
+    client.write_all(&header).await.unwrap();
+    assert!(receive(&mut server).await.is_err());
 }